@@ -2,7 +2,6 @@
 , rustPlatform
 , makeWrapper
 , teetty
-, coreutils
 }:
 
 rustPlatform.buildRustPackage rec {
@@ -22,7 +21,6 @@ rustPlatform.buildRustPackage rec {
     wrapProgram $out/bin/via \
       --prefix PATH : ${lib.makeBinPath [
         teetty      # Required for 'via run' command
-        coreutils   # Required for 'tail' in tail.rs
       ]}
   '';
 
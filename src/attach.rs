@@ -0,0 +1,163 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::fifo;
+use crate::session;
+use crate::tail;
+
+/// Detach key used when none is given: Ctrl-]
+pub const DEFAULT_DETACH_KEY: u8 = 0x1d;
+
+/// Lines of earlier output shown when attaching
+const BACKLOG_LINES: usize = 24;
+
+/// How an attachment ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ending {
+    /// The user pressed the detach key; the session keeps running
+    Detached,
+    /// The session's output went away
+    SessionEnded,
+}
+
+/// Parse a detach key: `^]`, `^A`, `ctrl-a` or `C-a` for a control character,
+/// or a single character
+pub fn parse_key(text: &str) -> Result<u8> {
+    let lower = text.to_ascii_lowercase();
+    let ctrl = lower.strip_prefix('^')
+        .or_else(|| lower.strip_prefix("ctrl-"))
+        .or_else(|| lower.strip_prefix("c-"));
+
+    let key = match ctrl {
+        Some(rest) if rest.len() == 1 => {
+            let c = rest.as_bytes()[0].to_ascii_uppercase();
+            match c {
+                b'@'..=b'_' => c - b'@',
+                b'?' => 0x7f,
+                _ => anyhow::bail!("invalid detach key: {}", text),
+            }
+        }
+        None if text.len() == 1 => text.as_bytes()[0],
+        _ => anyhow::bail!("invalid detach key: {} (use e.g. '^]' or 'ctrl-a')", text),
+    };
+    Ok(key)
+}
+
+/// Describe a key the way terminals show it, e.g. `^]`
+pub fn describe_key(key: u8) -> String {
+    match key {
+        0..=31 => format!("^{}", (key + b'@') as char),
+        0x7f => "^?".to_string(),
+        _ => (key as char).to_string(),
+    }
+}
+
+/// The terminal's original settings, restored when dropped
+struct RawMode {
+    fd: i32,
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable(fd: i32) -> Result<RawMode> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            anyhow::bail!("attach needs a terminal: {}", std::io::Error::last_os_error());
+        }
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            anyhow::bail!("failed to put the terminal in raw mode: {}", std::io::Error::last_os_error());
+        }
+        Ok(RawMode { fd, original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// Attach the local terminal to a session: show its recent output, then
+/// forward keystrokes to its input and mirror its output until `detach_key`
+/// is pressed or the session ends. The terminal is in raw mode meanwhile, so
+/// the session's program does its own echo and line editing.
+pub fn attach(session: &str, detach_key: u8) -> Result<Ending> {
+    let stdout_path = session::stdout_path(session)?;
+    let mut output = File::open(&stdout_path)
+        .with_context(|| format!("no stdout at {} (is the session running?)", stdout_path.display()))?;
+    let mut input = fifo::open_stdin(session)?;
+
+    let stdin = std::io::stdin();
+    let _raw = RawMode::enable(stdin.as_raw_fd())?;
+
+    // Mirror output from the last few lines on, until the session goes away
+    let pos = tail::last_lines_offset(&mut output, BACKLOG_LINES)?;
+    let tombstone_path = session::session_path(session)?.join("tombstone");
+    let ended = Arc::new(AtomicBool::new(false));
+    let mirror = {
+        let ended = Arc::clone(&ended);
+        thread::spawn(move || -> Result<()> {
+            let mut pos = pos;
+            let mut stdout = std::io::stdout();
+            loop {
+                // Checked before reading, so output written before the end is still shown
+                let gone = !stdout_path.exists() || tombstone_path.exists();
+                let size = output.seek(SeekFrom::End(0))?;
+                if size > pos {
+                    output.seek(SeekFrom::Start(pos))?;
+                    let mut data = Vec::new();
+                    (&mut output).take(size - pos).read_to_end(&mut data)?;
+                    stdout.write_all(&data)?;
+                    stdout.flush()?;
+                    pos = size;
+                } else if gone {
+                    ended.store(true, Ordering::SeqCst);
+                    return Ok(());
+                } else {
+                    thread::sleep(Duration::from_millis(20));
+                }
+            }
+        })
+    };
+
+    // Forward keystrokes, checking now and then whether the session ended
+    let fd = stdin.as_raw_fd();
+    let mut buffer = [0u8; 1024];
+    let ending = loop {
+        if ended.load(Ordering::SeqCst) {
+            break Ending::SessionEnded;
+        }
+
+        let mut poll = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut poll, 1, 100) } <= 0 {
+            continue;
+        }
+        let n = unsafe { libc::read(fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+        if n <= 0 {
+            break Ending::Detached;
+        }
+
+        let keys = &buffer[..n as usize];
+        match keys.iter().position(|&b| b == detach_key) {
+            Some(at) => {
+                input.write_all(&keys[..at]).with_context(|| "failed to write to session stdin")?;
+                break Ending::Detached;
+            }
+            None => input.write_all(keys).with_context(|| "failed to write to session stdin")?,
+        }
+    };
+
+    // The mirror thread only stops with the session; leave it running when detaching
+    if ending == Ending::SessionEnded {
+        let _ = mirror.join();
+    }
+    Ok(ending)
+}
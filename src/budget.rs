@@ -0,0 +1,119 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+
+use crate::session;
+
+// Output budgets keep a huge response from flooding whoever reads it (often an
+// agent with a limited context). Output over budget keeps its first and last
+// lines, and the middle is replaced by a marker pointing at the raw bytes:
+//
+//   [... 4211 lines omitted, see via ghci output 10234 88012 ...]
+//
+// The session itself is still read up to its prompt, so it stays in sync.
+
+/// Limits on how much output to print
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    pub max_lines: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl Budget {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_lines.is_none() && self.max_bytes.is_none()
+    }
+
+    /// How many lines to keep from the start and from the end of `lines`,
+    /// or None if they fit
+    fn keep(&self, lines: &[&str]) -> Option<(usize, usize)> {
+        let fits_lines = self.max_lines.is_none_or(|max| lines.len() <= max);
+        let fits_bytes = self.max_bytes.is_none_or(|max| lines.iter().map(|l| l.len() + 1).sum::<usize>() <= max);
+        if fits_lines && fits_bytes {
+            return None;
+        }
+
+        // Split the allowance evenly between head and tail
+        let (mut head, mut tail) = match self.max_lines {
+            Some(max) => (max - max / 2, max / 2),
+            None => (lines.len(), lines.len()),
+        };
+        if let Some(max) = self.max_bytes {
+            head = fit_bytes(lines.iter().take(head), max - max / 2);
+            tail = fit_bytes(lines.iter().rev().take(tail), max / 2);
+        }
+        if head + tail >= lines.len() {
+            return None;
+        }
+        Some((head, tail))
+    }
+}
+
+/// How many of `lines` fit in `max` bytes
+fn fit_bytes<'a>(lines: impl Iterator<Item = &'a &'a str>, max: usize) -> usize {
+    let mut used = 0;
+    lines.take_while(|line| {
+        used += line.len() + 1;
+        used <= max
+    }).count()
+}
+
+/// Apply `budget` to `text`, a session's output where `lines` holds the byte
+/// range in its stdout file of the raw line behind each line of text. The
+/// marker names the byte range of the omitted lines.
+pub fn elide(session: &str, text: &str, lines: &[Range<u64>], budget: &Budget) -> Result<String> {
+    let text_lines: Vec<&str> = text.lines().collect();
+    let Some((head, tail)) = budget.keep(&text_lines) else {
+        return Ok(text.to_string());
+    };
+    let omitted = text_lines.len() - head - tail;
+
+    // The omitted lines run from the start of the first to the end of the last
+    let offset = lines.get(head).map(|r| r.start).unwrap_or(0);
+    let end = lines.get(head + omitted - 1).map(|r| r.end).unwrap_or(offset);
+
+    let mut result = String::new();
+    for line in &text_lines[..head] {
+        result.push_str(line);
+        result.push('\n');
+    }
+    result.push_str(&format!(
+        "[... {} lines omitted, see via {} output {} {} ...]\n",
+        omitted, session, offset, end.saturating_sub(offset),
+    ));
+    for line in &text_lines[text_lines.len() - tail..] {
+        result.push_str(line);
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// The byte range of each line of `data`, raw output read from byte `start`
+/// of the stdout file
+pub fn raw_lines(data: &[u8], start: u64) -> Vec<Range<u64>> {
+    let mut pos = start;
+    data.split_inclusive(|&b| b == b'\n')
+        .map(|line| {
+            let range = pos..pos + line.len() as u64;
+            pos = range.end;
+            range
+        })
+        .collect()
+}
+
+/// `len` bytes (or everything) from byte `offset` of a session's output
+pub fn read_range(session: &str, offset: u64, len: Option<u64>) -> Result<Vec<u8>> {
+    let stdout_path = session::stdout_path(session)?;
+    let mut file = File::open(&stdout_path)
+        .with_context(|| format!("no stdout at {} (is the session running?)", stdout_path.display()))?;
+
+    let file_size = file.seek(SeekFrom::End(0))?;
+    if offset > file_size {
+        anyhow::bail!("offset {} is past the end of the output ({} bytes)", offset, file_size);
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::new();
+    file.take(len.unwrap_or(file_size - offset)).read_to_end(&mut data)?;
+    Ok(data)
+}
@@ -0,0 +1,337 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::ops::Range;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::fifo;
+use crate::session;
+use crate::tail::{self, Completion};
+use crate::tombstone;
+
+// Protocol: one request per connection. The client writes a single JSON object
+// on one line and reads a single JSON object back:
+//
+//   {"op": "ping"}
+//   {"op": "send", "input": "..."}
+//   {"op": "wait", "until": "PROMPT>", "timeout": 30, "offset": 0}
+//   {"op": "send_and_wait", "input": "...", "delim": "PROMPT>", "timeout": 30}
+//
+// Instead of a prompt, `wait` and `send_and_wait` accept `"idle": MS` to treat
+// the output as complete once the session has been silent for MS milliseconds,
+// `"shell": true` to delimit it by shell integration marks, or
+// `"sentinel": {"command": "...", "marker": "VIA-..."}` to send `command` after
+// the input (or, for `wait`, on its own) and end at the line it prints. With
+// none of these, the session's stored default is used. With `"stream": true`
+// they send the output as it arrives, a line at a time in `{"output": "..."}`
+// objects, before a final response without `output`. Their responses list in
+// `lines` the byte range in the output file behind each line of `output`,
+// which leaves out echoes and markers.
+//   {"op": "read", "offset": 0}
+//
+// Responses carry `"ok": true` plus op-specific fields, or `"ok": false` with an
+// `error` message (and `kind`/`found` when the session is not ready).
+
+/// How long a client waits for a response beyond the request's own timeout,
+/// which the server enforces
+const RESPONSE_GRACE: Duration = Duration::from_secs(5);
+
+/// Serve the session's control socket until the process exits.
+/// Each connection is handled on its own thread; requests that write input are
+/// serialized so one client's send-and-wait never interleaves with another's.
+pub fn serve(session: &str) -> Result<()> {
+    let path = session::control_path(session)?;
+    let _ = std::fs::remove_file(&path);
+
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("failed to bind {}", path.display()))?;
+    session::share_with_group(&path)?;
+    let input_lock = Arc::new(Mutex::new(()));
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let session = session.to_string();
+        let input_lock = Arc::clone(&input_lock);
+        thread::spawn(move || {
+            let _ = handle_connection(&session, stream, &input_lock);
+        });
+    }
+
+    Ok(())
+}
+
+/// Start serving the control socket on a background thread
+pub fn spawn_server(session: &str) {
+    let session = session.to_string();
+    thread::spawn(move || {
+        let _ = serve(&session);
+    });
+}
+
+fn handle_connection(session: &str, stream: UnixStream, input_lock: &Mutex<()>) -> Result<()> {
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = match serde_json::from_str::<Value>(&line) {
+        Ok(request) => {
+            let mut sink = if request["stream"].as_bool() == Some(true) {
+                Sink::Stream { stream: &stream, pending: Vec::new() }
+            } else {
+                Sink::Collect(Vec::new())
+            };
+            match dispatch(session, &request, input_lock, &mut sink) {
+                Ok(response) => response,
+                Err(err) => {
+                    // Whatever output was streamed before the error still goes out
+                    let _ = sink.flush();
+                    error_response(&err)
+                }
+            }
+        }
+        Err(err) => json!({ "ok": false, "error": format!("invalid request: {}", err) }),
+    };
+
+    let mut stream = stream;
+    writeln!(stream, "{}", response)?;
+    Ok(())
+}
+
+/// Where `wait` and `send_and_wait` put the output: collected for the
+/// response, or streamed to the client a line at a time as it arrives
+enum Sink<'a> {
+    Collect(Vec<u8>),
+    Stream { stream: &'a UnixStream, pending: Vec<u8> },
+}
+
+impl Sink<'_> {
+    /// Add the collected output to `response`, or send what's left of the
+    /// streamed output ahead of it
+    fn finish(&mut self, mut response: Value) -> Result<Value> {
+        match self {
+            Sink::Collect(output) => response["output"] = json!(String::from_utf8_lossy(output)),
+            Sink::Stream { .. } => self.flush()?,
+        }
+        Ok(response)
+    }
+
+    fn send_frame(stream: &UnixStream, data: &[u8]) -> std::io::Result<()> {
+        let mut stream = stream;
+        writeln!(stream, "{}", json!({ "output": String::from_utf8_lossy(data) }))
+    }
+}
+
+impl Write for Sink<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Sink::Collect(output) => output.extend_from_slice(buf),
+            Sink::Stream { stream, pending } => {
+                pending.extend_from_slice(buf);
+                if let Some(end) = pending.iter().rposition(|&b| b == b'\n') {
+                    let rest = pending.split_off(end + 1);
+                    Sink::send_frame(stream, pending)?;
+                    *pending = rest;
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Sink::Stream { stream, pending } = self {
+            if !pending.is_empty() {
+                Sink::send_frame(stream, pending)?;
+                pending.clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+fn dispatch(session: &str, request: &Value, input_lock: &Mutex<()>, sink: &mut Sink) -> Result<Value> {
+    let op = request["op"].as_str().unwrap_or("");
+    let timeout = match &request["timeout"] {
+        Value::Null => tail::DEFAULT_TIMEOUT,
+        value => value.as_f64().map(tail::check_timeout).transpose()?
+            .ok_or_else(|| anyhow::anyhow!("invalid timeout: {} (use a positive number of seconds)", value))?,
+    };
+
+    match op {
+        "ping" => Ok(json!({ "ok": true })),
+        "send" => {
+            let input = string_field(request, "input")?;
+            let _guard = input_lock.lock().unwrap_or_else(|e| e.into_inner());
+            fifo::write_input(session, input)?;
+            Ok(json!({ "ok": true }))
+        }
+        "wait" => {
+            let until = completion_field(session, request, "until")?;
+            let offset = request["offset"].as_u64().unwrap_or(0);
+            // Waiting on a sentinel writes it as input, so it must not land in
+            // the middle of another client's input or answer for theirs
+            let _guard = matches!(until, Completion::Sentinel { .. })
+                .then(|| input_lock.lock().unwrap_or_else(|e| e.into_inner()));
+            let mut lines = Vec::new();
+            let end = tail::wait(session, &until, timeout, offset, sink, &mut lines)?;
+            sink.finish(json!({ "ok": true, "end": end, "lines": ranges_json(&lines) }))
+        }
+        "send_and_wait" => {
+            let input = string_field(request, "input")?;
+            let delim = completion_field(session, request, "delim")?;
+            let _guard = input_lock.lock().unwrap_or_else(|e| e.into_inner());
+            let mut lines = Vec::new();
+            let (offset, end) = tail::send_and_follow(session, input, &delim, timeout, sink, &mut lines)?;
+            sink.finish(json!({ "ok": true, "offset": offset, "end": end, "lines": ranges_json(&lines) }))
+        }
+        "read" => {
+            let offset = request["offset"].as_u64().unwrap_or(0);
+            let (start, data, end) = tail::read_from_offset(session, offset)?;
+            Ok(json!({ "ok": true, "output": String::from_utf8_lossy(&data), "offset": start, "end": end }))
+        }
+        _ => anyhow::bail!("unknown op '{}'", op),
+    }
+}
+
+/// Byte ranges as `[[start, end], ...]`
+fn ranges_json(ranges: &[Range<u64>]) -> Value {
+    ranges.iter().map(|r| json!([r.start, r.end])).collect()
+}
+
+fn string_field<'a>(request: &'a Value, name: &str) -> Result<&'a str> {
+    request[name].as_str()
+        .ok_or_else(|| anyhow::anyhow!("missing '{}' in request", name))
+}
+
+/// Read how to detect completion from the request: a prompt in field `name`
+/// or an `idle` time, falling back to the session's stored delim or idle time
+fn completion_field(session: &str, request: &Value, name: &str) -> Result<Completion> {
+    if let Some(p) = request[name].as_str() {
+        return Ok(Completion::Prompt(p.to_string()));
+    }
+    if let Some(ms) = request["idle"].as_u64() {
+        return Ok(Completion::Idle(ms));
+    }
+    if request["shell"].as_bool() == Some(true) {
+        return Ok(Completion::Shell);
+    }
+    if let (Some(command), Some(marker)) = (request["sentinel"]["command"].as_str(), request["sentinel"]["marker"].as_str()) {
+        return Ok(Completion::Sentinel { command: command.to_string(), marker: marker.to_string() });
+    }
+    Ok(session::default_completion(session)?)
+}
+
+/// Serialize an error, keeping the details of typed errors so the client can
+/// rebuild them
+fn error_response(err: &anyhow::Error) -> Value {
+    match err.downcast_ref::<Error>() {
+        Some(Error::NotReady { prompt, found }) => json!({
+            "ok": false,
+            "kind": "not_ready",
+            "error": format!("{}", err),
+            "prompt": prompt,
+            "found": found,
+        }),
+        Some(Error::Timeout { pattern, secs }) => json!({
+            "ok": false,
+            "kind": "timeout",
+            "error": format!("{}", err),
+            "pattern": pattern,
+            "secs": secs,
+        }),
+        Some(Error::Ended { name, status }) => json!({
+            "ok": false,
+            "kind": "ended",
+            "error": format!("{}", err),
+            "name": name,
+            "status": status,
+        }),
+        _ => json!({ "ok": false, "error": format!("{:#}", err) }),
+    }
+}
+
+/// Send a request to the session's control socket and return the response.
+/// Returns `Ok(None)` when nothing is listening, so callers can fall back to
+/// driving the FIFO and stdout file directly.
+pub fn request(session: &str, request: &Value) -> Result<Option<Value>> {
+    request_streaming(session, request, &mut std::io::sink())
+}
+
+/// Like `request`, but writes any output the server streams (see `"stream"`)
+/// to `writer` as it arrives, and returns it as the response's `output`
+pub fn request_streaming(session: &str, request: &Value, writer: &mut dyn Write) -> Result<Option<Value>> {
+    let path = session::control_path(session)?;
+    let mut stream = match UnixStream::connect(&path) {
+        Ok(s) => s,
+        Err(_) => return Ok(None),
+    };
+
+    // The server gives up at the request's timeout; don't wait forever if it can't
+    let timeout = request["timeout"].as_f64().unwrap_or(tail::DEFAULT_TIMEOUT);
+    let deadline = tail::deadline(timeout)?;
+    let deadline = deadline.checked_add(RESPONSE_GRACE).unwrap_or(deadline);
+
+    writeln!(stream, "{}", request)
+        .with_context(|| format!("failed to write to {}", path.display()))?;
+    let mut reader = BufReader::new(&stream);
+    let mut streamed = String::new();
+    let mut response = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        stream.set_read_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(_) => {}
+            Err(err) if matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                let pattern = request["delim"].as_str().or(request["until"].as_str()).unwrap_or("a response");
+                return Err(Error::Timeout { pattern: pattern.to_string(), secs: timeout }.into());
+            }
+            Err(err) => return Err(err).with_context(|| format!("failed to read from {}", path.display())),
+        }
+        if line.is_empty() {
+            // The supervisor leaves a tombstone before it exits
+            tombstone::check_live(session)?;
+            anyhow::bail!("session closed the control connection");
+        }
+
+        let value: Value = serde_json::from_str(&line)
+            .with_context(|| "invalid response from control socket")?;
+        if value.get("ok").is_some() {
+            break value;
+        }
+        if let Some(output) = value["output"].as_str() {
+            writer.write_all(output.as_bytes())?;
+            writer.flush()?;
+            streamed.push_str(output);
+        }
+    };
+    if response.get("output").is_none() && request["stream"].as_bool() == Some(true) {
+        response["output"] = json!(streamed);
+    }
+
+    if response["ok"].as_bool() == Some(true) {
+        return Ok(Some(response));
+    }
+
+    match response["kind"].as_str() {
+        Some("not_ready") => return Err(Error::NotReady {
+            prompt: response["prompt"].as_str().unwrap_or("").to_string(),
+            found: response["found"].as_str().unwrap_or("").to_string(),
+        }.into()),
+        Some("timeout") => return Err(Error::Timeout {
+            pattern: response["pattern"].as_str().unwrap_or("").to_string(),
+            secs: response["secs"].as_f64().unwrap_or(0.0),
+        }.into()),
+        Some("ended") => return Err(Error::Ended {
+            name: response["name"].as_str().unwrap_or(session).to_string(),
+            status: response["status"].as_str().unwrap_or("").to_string(),
+        }.into()),
+        _ => {}
+    }
+
+    anyhow::bail!("{}", response["error"].as_str().unwrap_or("control request failed"))
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned by the `Session` API
+#[derive(Debug)]
+pub enum Error {
+    /// There is no session directory with this name
+    NotFound(String),
+    /// The name can't be used for a session directory
+    InvalidName { name: String, reason: String },
+    /// Another session already holds this name
+    AlreadyExists(String),
+    /// The session's command has exited; its tombstone says how
+    Ended { name: String, status: String },
+    /// No delimiter was given and the session has none stored
+    NoDelim(String),
+    /// The output doesn't end with the expected prompt, so the session may
+    /// still be busy with earlier input
+    NotReady { prompt: String, found: String },
+    /// The pattern didn't appear before the timeout
+    Timeout { pattern: String, secs: f64 },
+    /// Reading or writing session files failed
+    Io(io::Error),
+    /// Anything else, with its context chain flattened into a message
+    Other(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// A stable, machine-readable name for the kind of error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "not_found",
+            Error::InvalidName { .. } => "invalid_name",
+            Error::AlreadyExists(_) => "already_exists",
+            Error::Ended { .. } => "ended",
+            Error::NoDelim(_) => "no_delim",
+            Error::NotReady { .. } => "not_ready",
+            Error::Timeout { .. } => "timeout",
+            Error::Io(_) => "io",
+            Error::Other(_) => "other",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(name) => write!(f, "unknown session '{}'", name),
+            Error::InvalidName { name, reason } => write!(f, "invalid session name '{}': {}", name, reason),
+            Error::AlreadyExists(name) => write!(f, "session '{}' already exists", name),
+            Error::Ended { name, status } => write!(f, "session '{}' has ended ({})", name, status),
+            Error::NoDelim(name) => write!(f, "no stored delimiter for session '{}' (use --delim VALUE with 'via run')", name),
+            Error::NotReady { .. } => write!(f, "Session may contain unprocessed input"),
+            Error::Timeout { pattern, secs } => write!(f, "timeout waiting for '{}' (after {}s)", pattern, secs),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// Internals use anyhow and raise typed errors where callers need to tell
+/// them apart; recover those here and flatten everything else.
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<Error>() {
+            Ok(err) => err,
+            Err(err) => Error::Other(format!("{:#}", err)),
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use anyhow::Result;
+use serde_json::json;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use crate::index::{self, Checkpoint};
+use crate::session;
+use crate::stanza::{self, Stanza};
+
+/// Transcript formats understood by `via <session> export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// asciinema v2 recording, raw terminal output with colors
+    Asciicast,
+    /// Markdown with one heading and code block per input
+    Markdown,
+    /// Standalone HTML page, ANSI colors rendered as styled spans
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "asciicast" | "cast" => Ok(Format::Asciicast),
+            "markdown" | "md" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            _ => anyhow::bail!("unknown export format '{}' (expected asciicast, markdown or html)", s),
+        }
+    }
+}
+
+/// A session's output split into its startup banner and input/output exchanges
+struct Transcript {
+    session: String,
+    command: String,
+    /// Launch time, in milliseconds since the epoch
+    started_ms: u64,
+    checkpoints: Vec<Checkpoint>,
+    data: Vec<u8>,
+    preamble_end: u64,
+    stanzas: Vec<Stanza>,
+}
+
+impl Transcript {
+    fn load(session: &str, delim: Option<&str>) -> Result<Self> {
+        let dir = session::session_path(session)?;
+        let data = stanza::read_output(session)?;
+        let command = std::fs::read_to_string(dir.join("command")).unwrap_or_default();
+        let started_ms = std::fs::metadata(dir.join("command"))
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let checkpoints = index::load(session)?;
+
+        let delim = match delim {
+            Some(d) => Some(d.to_string()),
+            None => session::get_delim(session)?,
+        };
+        // Without a prompt the whole output is one block
+        let (preamble_end, stanzas) = match delim {
+            Some(ref d) => stanza::split(&data, d),
+            None => (data.len() as u64, Vec::new()),
+        };
+
+        Ok(Transcript {
+            session: session.to_string(),
+            command,
+            started_ms,
+            checkpoints,
+            data,
+            preamble_end,
+            stanzas,
+        })
+    }
+
+    fn bytes(&self, start: u64, end: u64) -> &[u8] {
+        &self.data[start as usize..end as usize]
+    }
+
+    fn preamble(&self) -> &[u8] {
+        self.bytes(0, self.preamble_end)
+    }
+
+    /// Exchanges worth showing: skips stanzas with neither input nor output,
+    /// such as the prompt still waiting for input at the end
+    fn exchanges(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.stanzas.iter()
+            .map(|s| (s.input.as_str(), self.bytes(s.body, s.end)))
+            .filter(|(input, body)| !input.is_empty() || !plain_text(body).is_empty())
+    }
+}
+
+/// Write a transcript of the session's output in the given format.
+/// `delim` overrides the stored prompt used to split the output into exchanges.
+pub fn export(session: &str, format: Format, delim: Option<&str>, out: &mut dyn Write) -> Result<()> {
+    let transcript = Transcript::load(session, delim)?;
+    match format {
+        Format::Asciicast => write_asciicast(&transcript, out),
+        Format::Markdown => write_markdown(&transcript, out),
+        Format::Html => write_html(&transcript, out),
+    }
+}
+
+fn write_asciicast(t: &Transcript, out: &mut dyn Write) -> Result<()> {
+    let header = json!({
+        "version": 2,
+        "width": 80,
+        "height": 24,
+        "timestamp": t.started_ms / 1000,
+        "title": format!("via {}: {}", t.session, t.command),
+    });
+    writeln!(out, "{}", header)?;
+
+    let mut events: Vec<(f64, &[u8])> = Vec::new();
+    if t.checkpoints.is_empty() {
+        // Without an index there is no timing, so the banner plays at once
+        // and each stanza follows a second after the previous one.
+        events.push((0.0, t.preamble()));
+        for (i, s) in t.stanzas.iter().enumerate() {
+            events.push(((i + 1) as f64, t.bytes(s.start, s.end)));
+        }
+    } else {
+        // One event per checkpoint, with the bytes written since the previous one
+        let mut start = 0;
+        let checkpoints = t.checkpoints.iter()
+            .map(|c| (c.time_ms, c.offset))
+            .chain(std::iter::once((index::now_ms(), t.data.len() as u64)));
+        for (time_ms, offset) in checkpoints {
+            let end = char_boundary(&t.data, offset.min(t.data.len() as u64) as usize);
+            if end > start {
+                let time = time_ms.saturating_sub(t.started_ms) as f64 / 1000.0;
+                events.push((time, &t.data[start..end]));
+                start = end;
+            }
+        }
+    }
+
+    for (time, data) in events {
+        if data.is_empty() {
+            continue;
+        }
+        writeln!(out, "{}", json!([time, "o", String::from_utf8_lossy(data)]))?;
+    }
+    Ok(())
+}
+
+/// Move `pos` back to the start of a UTF-8 character so events don't split one
+fn char_boundary(data: &[u8], mut pos: usize) -> usize {
+    while pos > 0 && pos < data.len() && (data[pos] & 0xc0) == 0x80 {
+        pos -= 1;
+    }
+    pos
+}
+
+fn write_markdown(t: &Transcript, out: &mut dyn Write) -> Result<()> {
+    writeln!(out, "# via session {}", inline_code(&t.session))?;
+    writeln!(out)?;
+    writeln!(out, "Command: {}", inline_code(t.command.trim()))?;
+
+    let banner = plain_text(t.preamble());
+    if !banner.is_empty() {
+        writeln!(out)?;
+        write_fenced(out, &banner)?;
+    }
+
+    for (i, (input, body)) in t.exchanges().enumerate() {
+        writeln!(out)?;
+        writeln!(out, "## {}. {}", i + 1, inline_code(input))?;
+        let text = plain_text(body);
+        if !text.is_empty() {
+            writeln!(out)?;
+            write_fenced(out, &text)?;
+        }
+    }
+    Ok(())
+}
+
+/// Wrap text in a fenced code block, using a fence longer than any backtick
+/// run inside it
+fn write_fenced(out: &mut dyn Write, text: &str) -> Result<()> {
+    let fence = "`".repeat(std::cmp::max(3, longest_backtick_run(text) + 1));
+    writeln!(out, "{}text", fence)?;
+    writeln!(out, "{}", text)?;
+    writeln!(out, "{}", fence)?;
+    Ok(())
+}
+
+fn inline_code(text: &str) -> String {
+    let ticks = "`".repeat(longest_backtick_run(text) + 1);
+    if text.starts_with('`') || text.ends_with('`') {
+        format!("{} {} {}", ticks, text, ticks)
+    } else {
+        format!("{}{}{}", ticks, text, ticks)
+    }
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+/// Render output as plain text: escape sequences stripped, carriage-return
+/// overwrites resolved per line, trailing blank lines dropped
+fn plain_text(data: &[u8]) -> String {
+    let lines: Vec<String> = data.split(|&b| b == b'\n')
+        .map(|line| {
+            let text = crate::prompt::strip_ansi(line);
+            let text = text.trim_end_matches('\r');
+            let text = text.rsplit('\r').next().unwrap_or("");
+            text.chars()
+                .filter(|&c| c != '\x07' && c != '\x08' && c != '\x7F')
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+    lines.join("\n").trim_end().trim_start_matches('\n').to_string()
+}
+
+const HTML_STYLE: &str = "body { background: #1e1e1e; color: #d4d4d4; font-family: sans-serif; }
+pre { background: #000; color: #e5e5e5; padding: 0.75em; overflow-x: auto; }
+code { font-family: monospace; }
+h2 code { background: #333; padding: 0.1em 0.3em; }";
+
+fn write_html(t: &Transcript, out: &mut dyn Write) -> Result<()> {
+    let title = format!("via session {}", t.session);
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html>")?;
+    writeln!(out, "<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>", escape_html(&title))?;
+    writeln!(out, "<style>\n{}\n</style>", HTML_STYLE)?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body>")?;
+    writeln!(out, "<h1>via session <code>{}</code></h1>", escape_html(&t.session))?;
+    writeln!(out, "<p>Command: <code>{}</code></p>", escape_html(t.command.trim()))?;
+
+    if !plain_text(t.preamble()).is_empty() {
+        writeln!(out, "<pre>{}</pre>", ansi_to_html(t.preamble()))?;
+    }
+
+    for (input, body) in t.exchanges() {
+        writeln!(out, "<h2><code>{}</code></h2>", escape_html(input))?;
+        if !plain_text(body).is_empty() {
+            writeln!(out, "<pre>{}</pre>", ansi_to_html(body))?;
+        }
+    }
+
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")?;
+    Ok(())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Text attributes set by SGR escape sequences
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Style {
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    fg: Option<String>,
+    bg: Option<String>,
+}
+
+impl Style {
+    fn css(&self) -> String {
+        let mut css = Vec::new();
+        if self.bold {
+            css.push("font-weight: bold".to_string());
+        }
+        if self.dim {
+            css.push("opacity: 0.7".to_string());
+        }
+        if self.italic {
+            css.push("font-style: italic".to_string());
+        }
+        if self.underline {
+            css.push("text-decoration: underline".to_string());
+        }
+        if let Some(ref fg) = self.fg {
+            css.push(format!("color: {}", fg));
+        }
+        if let Some(ref bg) = self.bg {
+            css.push(format!("background-color: {}", bg));
+        }
+        css.join("; ")
+    }
+
+    /// Apply the parameters of one SGR sequence (`ESC [ params m`)
+    fn apply(&mut self, params: &[u16]) {
+        let mut i = 0;
+        if params.is_empty() {
+            *self = Style::default();
+        }
+        while i < params.len() {
+            match params[i] {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                n @ 30..=37 => self.fg = Some(palette(n - 30)),
+                n @ 90..=97 => self.fg = Some(palette(n - 90 + 8)),
+                n @ 40..=47 => self.bg = Some(palette(n - 40)),
+                n @ 100..=107 => self.bg = Some(palette(n - 100 + 8)),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                n @ (38 | 48) => {
+                    let (color, used) = extended_color(&params[i + 1..]);
+                    if n == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                    i += used;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Parse the tail of a `38;5;N` or `38;2;R;G;B` sequence. Returns the color
+/// and how many parameters it used.
+fn extended_color(params: &[u16]) -> (Option<String>, usize) {
+    match params {
+        [5, n, ..] => (Some(palette(*n)), 2),
+        [2, r, g, b, ..] => (Some(format!("#{:02x}{:02x}{:02x}", r, g, b)), 4),
+        _ => (None, params.len()),
+    }
+}
+
+/// CSS color for an xterm 256-color palette index
+fn palette(n: u16) -> String {
+    const BASIC: [&str; 16] = [
+        "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd", "#e5e5e5",
+        "#7f7f7f", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff", "#00ffff", "#ffffff",
+    ];
+    match n {
+        0..=15 => BASIC[n as usize].to_string(),
+        16..=231 => {
+            let level = |v: u16| if v == 0 { 0 } else { 55 + v * 40 };
+            let n = n - 16;
+            format!("#{:02x}{:02x}{:02x}", level(n / 36), level((n / 6) % 6), level(n % 6))
+        }
+        _ => {
+            let grey = 8 + (n.min(255) - 232) * 10;
+            format!("#{:02x}{:02x}{:02x}", grey, grey, grey)
+        }
+    }
+}
+
+/// Convert terminal output to HTML, rendering SGR colors and attributes as
+/// styled spans and dropping every other escape sequence
+fn ansi_to_html(data: &[u8]) -> String {
+    let mut html = String::new();
+    let mut style = Style::default();
+    let mut open = false;
+
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        let (line, newline) = match line.strip_suffix(b"\n") {
+            Some(l) => (l, true),
+            None => (line, false),
+        };
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        // A carriage return inside a line redraws it; keep what was drawn last
+        let line = match line.iter().rposition(|&b| b == b'\r') {
+            Some(i) => &line[i + 1..],
+            None => line,
+        };
+
+        let mut text: Vec<u8> = Vec::new();
+        let mut i = 0;
+        while i < line.len() {
+            if line[i] != 0x1b {
+                if !matches!(line[i], 0x07 | 0x08 | 0x7f) {
+                    text.push(line[i]);
+                }
+                i += 1;
+                continue;
+            }
+
+            // Flush the text written in the current style
+            html.push_str(&escape_html(&String::from_utf8_lossy(&text)));
+            text.clear();
+
+            match line.get(i + 1) {
+                Some(b'[') => {
+                    let start = i + 2;
+                    let mut end = start;
+                    while end < line.len() && !(0x40..=0x7e).contains(&line[end]) {
+                        end += 1;
+                    }
+                    if line.get(end) == Some(&b'm') {
+                        let params: Vec<u16> = String::from_utf8_lossy(&line[start..end])
+                            .split(';')
+                            .filter_map(|p| if p.is_empty() { Some(0) } else { p.parse().ok() })
+                            .collect();
+                        let mut next = style.clone();
+                        next.apply(&params);
+                        if next != style {
+                            if open {
+                                html.push_str("</span>");
+                            }
+                            style = next;
+                            open = style != Style::default();
+                            if open {
+                                html.push_str(&format!("<span style=\"{}\">", style.css()));
+                            }
+                        }
+                    }
+                    i = end + 1;
+                }
+                Some(b']') => {
+                    // OSC: skip to BEL or ESC backslash
+                    let mut end = i + 2;
+                    while end < line.len() && line[end] != 0x07 && !(line[end] == 0x1b && line.get(end + 1) == Some(&b'\\')) {
+                        end += 1;
+                    }
+                    i = if line.get(end) == Some(&0x1b) { end + 2 } else { end + 1 };
+                }
+                _ => i += 2,
+            }
+        }
+        html.push_str(&escape_html(&String::from_utf8_lossy(&text)));
+        if newline {
+            html.push('\n');
+        }
+    }
+
+    if open {
+        html.push_str("</span>");
+    }
+    html.trim_end_matches('\n').to_string()
+}
@@ -1,39 +1,89 @@
 use anyhow::{Context, Result};
-use std::fs::OpenOptions;
-use std::io::{self, Write, BufRead, BufReader};
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::{Write, BufRead};
 
+use crate::index;
 use crate::session;
+use crate::tombstone;
 
-/// Write to a session's stdin pipe
-pub fn write_session(session_name: &str, args: &[String]) -> Result<()> {
+/// Open a session's stdin pipe for writing
+pub fn open_stdin(session_name: &str) -> Result<File> {
     let stdin_path = session::stdin_path(session_name)?;
 
     if !stdin_path.exists() {
+        tombstone::check_live(session_name)?;
         anyhow::bail!("no stdin at {} (is the session running?)", stdin_path.display());
     }
 
-    let mut file = OpenOptions::new()
-        .write(true)
+    OpenOptions::new()
         .append(true)
         .open(&stdin_path)
-        .with_context(|| format!("failed to open {}", stdin_path.display()))?;
+        .with_context(|| format!("failed to open {}", stdin_path.display()))
+}
+
+/// Forward lines from `reader` to a session's stdin pipe as they arrive
+pub fn forward_lines(session_name: &str, reader: impl BufRead) -> Result<()> {
+    let mut file = open_stdin(session_name)?;
 
-    if !args.is_empty() {
-        // Write args as a single line
-        let line = args.join(" ");
+    for line in reader.lines() {
+        let line = line.with_context(|| "failed to read input")?;
+        log_input(session_name, &line)?;
         writeln!(file, "{}", line)
             .with_context(|| "failed to write to session stdin")?;
-    } else {
-        // Read from stdin and forward to the pipe
-        let stdin = io::stdin();
-        let reader = BufReader::new(stdin);
-
-        for line in reader.lines() {
-            let line = line.with_context(|| "failed to read from stdin")?;
-            writeln!(file, "{}", line)
-                .with_context(|| "failed to write to session stdin")?;
-        }
     }
 
     Ok(())
 }
+
+/// Write input to a session's stdin pipe, making sure it ends with a newline
+pub fn write_input(session_name: &str, input: &str) -> Result<()> {
+    let mut file = open_stdin(session_name)?;
+    log_input(session_name, input.strip_suffix('\n').unwrap_or(input))?;
+
+    file.write_all(input.as_bytes())
+        .with_context(|| "failed to write to session stdin")?;
+    if !input.ends_with('\n') {
+        writeln!(file).with_context(|| "failed to write to session stdin")?;
+    }
+
+    Ok(())
+}
+
+/// Write a sentinel command after `input` (the input just written, if any).
+/// It is not part of the conversation, so it stays out of the input log.
+pub fn write_sentinel(session_name: &str, input: &str, command: &str) -> Result<()> {
+    let mut file = open_stdin(session_name)?;
+    // A REPL reads lines after a multi-line block as part of it until a blank
+    // line ends the block, and would never run the sentinel
+    let body = input.trim_end_matches('\n');
+    if body.contains('\n') && !input.ends_with("\n\n") {
+        writeln!(file).with_context(|| "failed to write to session stdin")?;
+    }
+    writeln!(file, "{}", command).with_context(|| "failed to write to session stdin")
+}
+
+/// Get the input log path for a session
+pub fn inputs_path(session_name: &str) -> Result<std::path::PathBuf> {
+    Ok(session::session_path(session_name)?.join("inputs"))
+}
+
+/// Append an input to the session's input log, one JSON object per line with
+/// the time it was sent and the length of the output at that moment, so
+/// recordings can pair each input with the output that followed it
+fn log_input(session_name: &str, input: &str) -> Result<()> {
+    let path = inputs_path(session_name)?;
+    let offset = std::fs::metadata(session::stdout_path(session_name)?)
+        .map(|m| m.len())
+        .unwrap_or(0);
+
+    let created = !path.exists();
+    let mut log = OpenOptions::new().create(true).append(true).open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    if created {
+        session::share_with_group(&path)?;
+    }
+
+    writeln!(log, "{}", json!({ "time": index::now_ms(), "offset": offset, "input": input }))
+        .with_context(|| format!("failed to write {}", path.display()))
+}
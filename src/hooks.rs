@@ -0,0 +1,306 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::index;
+use crate::prompt;
+use crate::shell;
+
+// Hooks run a command when something happens to a session. Each one is
+// written `EVENT=COMMAND`, given with `run --hook` (stored in the session's
+// `hooks` file) or listed one per line in the user's `~/.config/via/hooks`,
+// which applies to every session. The events are:
+//
+//   start          the command was launched (again, after a restart)
+//   ready          its first prompt appeared after a launch
+//   exit           the command exited (before any restart)
+//   match:REGEX    a line of output matched REGEX (escape sequences removed)
+//
+// The supervisor runs the command with `sh -c`, in the background, with
+// VIA_SESSION, VIA_SESSION_DIR and VIA_EVENT set, plus VIA_MATCH (the line)
+// for match hooks and VIA_EXIT_CODE or VIA_SIGNAL for exit hooks. It waits for
+// exit hooks to finish, so they can still read the session's output, but kills
+// any still running after 30 seconds.
+
+/// How often the watcher looks at the output
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Silence that marks startup as done for sessions without a prompt or idle time
+const READY_IDLE_MS: u64 = 500;
+
+/// Longest the supervisor waits for exit hooks
+const EXIT_HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Something that happens to a session
+#[derive(Debug, Clone)]
+pub enum Event {
+    Start,
+    Ready,
+    Exit,
+    Match(Regex),
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Start => "start",
+            Event::Ready => "ready",
+            Event::Exit => "exit",
+            Event::Match(_) => "match",
+        }
+    }
+}
+
+/// A command to run on an event
+#[derive(Debug, Clone)]
+pub struct Hook {
+    pub event: Event,
+    pub command: String,
+}
+
+/// Parse a hook written `EVENT=COMMAND`. The event ends at the first `=` that
+/// isn't escaped, so a match pattern writes a literal one as `\=`.
+pub fn parse(spec: &str) -> Result<Hook> {
+    let at = spec.char_indices()
+        .find(|&(i, c)| c == '=' && !spec[..i].ends_with('\\'))
+        .map(|(i, _)| i)
+        .ok_or_else(|| anyhow::anyhow!("invalid hook: {} (use EVENT=COMMAND)", spec))?;
+    let (event, command) = (&spec[..at], &spec[at + 1..]);
+    let event = match event.trim() {
+        "start" => Event::Start,
+        "ready" => Event::Ready,
+        "exit" => Event::Exit,
+        other => match other.strip_prefix("match:") {
+            Some(pattern) => Event::Match(Regex::new(pattern)
+                .with_context(|| format!("invalid hook pattern: {}", pattern))?),
+            None => anyhow::bail!("unknown hook event: {} (use start, ready, exit or match:REGEX)", other),
+        },
+    };
+    let command = command.trim();
+    if command.is_empty() {
+        anyhow::bail!("hook for '{}' has no command", event.name());
+    }
+    Ok(Hook { event, command: command.to_string() })
+}
+
+/// The user's hooks file: `$XDG_CONFIG_HOME/via/hooks`, or `~/.config/via/hooks`
+pub fn user_config_path() -> Option<PathBuf> {
+    let config = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(".config"),
+    };
+    Some(config.join("via").join("hooks"))
+}
+
+/// Parse hooks one per line, skipping blank lines and `#` comments
+fn parse_file(path: &Path) -> Result<Vec<Hook>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return Ok(Vec::new()),
+    };
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| parse(line).with_context(|| format!("in {}", path.display())))
+        .collect()
+}
+
+/// The hooks for the session in `dir`: the user's, then the session's own
+pub fn load(dir: &Path) -> Result<Vec<Hook>> {
+    let mut hooks = match user_config_path() {
+        Some(path) => parse_file(&path)?,
+        None => Vec::new(),
+    };
+    hooks.extend(parse_file(&dir.join("hooks"))?);
+    Ok(hooks)
+}
+
+/// Run a hook's command in the background with the session's details (and
+/// `extra`) in its environment
+fn spawn(hook: &Hook, dir: &Path, extra: &[(&str, String)]) -> Option<std::process::Child> {
+    let name = dir.file_name()?.to_string_lossy().into_owned();
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(&hook.command)
+       .env("VIA_SESSION", name)
+       .env("VIA_SESSION_DIR", dir)
+       .env("VIA_EVENT", hook.event.name())
+       .stdin(Stdio::null())
+       .stdout(Stdio::null())
+       .stderr(Stdio::null())
+       .process_group(0);
+    for (key, value) in extra {
+        cmd.env(key, value);
+    }
+    cmd.spawn().ok()
+}
+
+/// Run the hooks for an event that carries no details, reaping them on a thread
+pub fn fire(hooks: &[Hook], dir: &Path, event: &Event) {
+    for hook in hooks.iter().filter(|h| h.event.name() == event.name()) {
+        if let Some(mut child) = spawn(hook, dir, &[]) {
+            thread::spawn(move || child.wait());
+        }
+    }
+}
+
+/// Run the exit hooks and wait (for a while) until they're done
+pub fn fire_exit(hooks: &[Hook], dir: &Path, status: &ExitStatus) {
+    let mut extra = Vec::new();
+    if let Some(code) = status.code() {
+        extra.push(("VIA_EXIT_CODE", code.to_string()));
+    }
+    if let Some(signal) = status.signal() {
+        extra.push(("VIA_SIGNAL", signal.to_string()));
+    }
+
+    let mut children: Vec<_> = hooks.iter()
+        .filter(|h| matches!(h.event, Event::Exit))
+        .filter_map(|hook| spawn(hook, dir, &extra).map(|child| (hook, child)))
+        .collect();
+    let deadline = Instant::now() + EXIT_HOOK_TIMEOUT;
+    while Instant::now() < deadline {
+        children.retain_mut(|(_, child)| matches!(child.try_wait(), Ok(None)));
+        if children.is_empty() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    // Don't leave hooks that overran running (or as zombies) behind us
+    for (hook, mut child) in children {
+        // The hook runs in its own process group, so this takes its children too
+        unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+        let _ = child.wait();
+        log(dir, &format!("exit hook timed out after {}s and was killed: {}",
+                          EXIT_HOOK_TIMEOUT.as_secs(), hook.command));
+    }
+}
+
+/// Report a problem with a hook on stderr (seen in the foreground) and in the
+/// session's `hooks.log` (kept with the tombstone)
+fn log(dir: &Path, message: &str) {
+    use std::io::Write;
+    eprintln!("[via] {}", message);
+    if let Ok(mut file) = fs::OpenOptions::new().create(true).append(true).open(dir.join("hooks.log")) {
+        let _ = writeln!(file, "{} {}", index::format_time(index::now_ms()), message);
+    }
+}
+
+/// Start watching the session's output for ready and match hooks on a
+/// background thread, if it has any
+pub fn spawn_watcher(dir: &Path, hooks: &[Hook]) {
+    let hooks: Vec<Hook> = hooks.iter()
+        .filter(|h| matches!(h.event, Event::Ready | Event::Match(_)))
+        .cloned()
+        .collect();
+    if hooks.is_empty() {
+        return;
+    }
+    let dir = dir.to_path_buf();
+    thread::spawn(move || {
+        let _ = watch(&dir, &hooks);
+    });
+}
+
+/// Whether the output so far shows the session's first prompt: its stored
+/// delim, the end of a shell integration prompt, or else a pause after some
+/// output
+fn shows_prompt(dir: &Path, pending: &[u8], quiet_for: Duration) -> bool {
+    if dir.join("shell").exists() {
+        return shell::find_mark(pending, b'B').is_some();
+    }
+    if let Ok(delim) = fs::read_to_string(dir.join("delim")) {
+        return prompt::strip_ansi(pending).contains(&delim);
+    }
+    let idle = fs::read_to_string(dir.join("idle")).ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(READY_IDLE_MS);
+    quiet_for >= Duration::from_millis(idle)
+}
+
+/// Follow the output until the session ends, running ready hooks at each
+/// launch's first prompt and match hooks for every matching line
+fn watch(dir: &Path, hooks: &[Hook]) -> Result<()> {
+    let stdout_path = dir.join("stdout");
+    let mut pos = 0;
+    let mut line = Vec::new();
+    // Output since the last launch, until its prompt shows
+    let mut startup = Vec::new();
+    let mut ready = false;
+    let mut restarts = 0;
+    let mut last_change = Instant::now();
+
+    loop {
+        // Checked before reading, so output written before the end is still seen
+        let ended = dir.join("tombstone").exists();
+        let mut file = match File::open(&stdout_path) {
+            Ok(f) => f,
+            Err(_) if !dir.is_dir() || ended => return Ok(()),
+            Err(_) => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+        };
+
+        // A restart launches the command again: wait for its prompt anew
+        let count = fs::read_to_string(dir.join("restarts")).map(|t| t.lines().count()).unwrap_or(0);
+        if count != restarts {
+            restarts = count;
+            ready = false;
+            startup.clear();
+            last_change = Instant::now();
+        }
+
+        let size = file.seek(SeekFrom::End(0))?;
+        if size < pos {
+            pos = 0;
+        }
+        if size > pos {
+            file.seek(SeekFrom::Start(pos))?;
+            let mut data = Vec::new();
+            (&mut file).take(size - pos).read_to_end(&mut data)?;
+            pos = size;
+            last_change = Instant::now();
+
+            if !ready {
+                startup.extend_from_slice(&data);
+            }
+            for &b in &data {
+                if b != b'\n' {
+                    line.push(b);
+                    continue;
+                }
+                let text = prompt::strip_ansi(&line);
+                let text = text.trim_end_matches('\r');
+                for hook in hooks {
+                    if let Event::Match(ref regex) = hook.event {
+                        if regex.is_match(text) {
+                            if let Some(mut child) = spawn(hook, dir, &[("VIA_MATCH", text.to_string())]) {
+                                thread::spawn(move || child.wait());
+                            }
+                        }
+                    }
+                }
+                line.clear();
+            }
+        }
+
+        if !ready && !startup.is_empty() && shows_prompt(dir, &startup, last_change.elapsed()) {
+            ready = true;
+            startup.clear();
+            fire(hooks, dir, &Event::Ready);
+        }
+        if ended {
+            return Ok(());
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
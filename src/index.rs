@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::session;
+use crate::tail;
+
+// Timing sidecar: the supervisor polls the size of the stdout file and appends
+// a checkpoint line `<unix time in ms> <byte offset>` to the session's `index`
+// file whenever it has grown. Bytes between two checkpoints were written in the
+// interval between them, so byte offsets map to times within one poll interval.
+
+/// How often the recorder checks the stdout file for new output
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The output file was `offset` bytes long at `time_ms`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub time_ms: u64,
+    pub offset: u64,
+}
+
+/// Get the index file path for a session
+pub fn index_path(session: &str) -> Result<PathBuf> {
+    Ok(session::session_path(session)?.join("index"))
+}
+
+/// Current time in milliseconds since the epoch
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Record checkpoints until the session's stdout file goes away
+pub fn record(session: &str) -> Result<()> {
+    let stdout_path = session::stdout_path(session)?;
+    let index_path = index_path(session)?;
+
+    // Wait for teetty to create the output file
+    while !stdout_path.exists() {
+        if !index_path.parent().is_some_and(|dir| dir.exists()) {
+            return Ok(());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let mut index = OpenOptions::new().create(true).append(true).open(&index_path)
+        .with_context(|| format!("failed to open {}", index_path.display()))?;
+    session::share_with_group(&index_path)?;
+
+    let mut last = 0;
+    loop {
+        let size = match fs::metadata(&stdout_path) {
+            Ok(meta) => meta.len(),
+            Err(_) => return Ok(()),
+        };
+        if size != last {
+            writeln!(index, "{} {}", now_ms(), size)?;
+            last = size;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Start recording checkpoints on a background thread
+pub fn spawn_recorder(session: &str) {
+    let session = session.to_string();
+    thread::spawn(move || {
+        let _ = record(&session);
+    });
+}
+
+/// Load a session's checkpoints. Sessions started before the index existed
+/// have none.
+pub fn load(session: &str) -> Result<Vec<Checkpoint>> {
+    let path = index_path(session)?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    Ok(text.lines()
+        .filter_map(|line| {
+            let (time, offset) = line.split_once(' ')?;
+            Some(Checkpoint { time_ms: time.parse().ok()?, offset: offset.parse().ok()? })
+        })
+        .collect())
+}
+
+/// When the byte at `offset` was written: the time of the first checkpoint
+/// that covers it. Output newer than the last checkpoint was written just now.
+pub fn time_of(checkpoints: &[Checkpoint], offset: u64) -> u64 {
+    checkpoints.iter()
+        .find(|c| c.offset > offset)
+        .map(|c| c.time_ms)
+        .unwrap_or_else(now_ms)
+}
+
+/// Offset of the first byte written after `time_ms`
+pub fn offset_since(checkpoints: &[Checkpoint], time_ms: u64) -> u64 {
+    checkpoints.iter()
+        .take_while(|c| c.time_ms <= time_ms)
+        .last()
+        .map(|c| c.offset)
+        .unwrap_or(0)
+}
+
+/// The session's output lines written since `time_ms`, each with the time it
+/// was written. A trailing partial line (such as a prompt) is included.
+pub fn lines_since(session: &str, time_ms: u64) -> Result<Vec<(u64, Vec<u8>)>> {
+    let checkpoints = load(session)?;
+    let (start, data, _) = tail::read_from_offset(session, offset_since(&checkpoints, time_ms))?;
+
+    let mut offset = start;
+    let mut lines = Vec::new();
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        lines.push((time_of(&checkpoints, offset), line.to_vec()));
+        offset += line.len() as u64;
+    }
+    Ok(lines)
+}
+
+/// Parse a duration like `90`, `30s`, `5m`, `2h` or `1d` (bare numbers are seconds)
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(i) => text.split_at(i),
+        None => (text, "s"),
+    };
+    let value: f64 = number.parse()
+        .with_context(|| format!("invalid duration: {}", text))?;
+    let secs = match unit {
+        "ms" => value / 1000.0,
+        "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        "d" => value * 86400.0,
+        _ => anyhow::bail!("invalid duration: {} (use s, m, h or d)", text),
+    };
+    match Duration::try_from_secs_f64(secs) {
+        Ok(duration) => Ok(duration),
+        Err(_) => anyhow::bail!("invalid duration: {}", text),
+    }
+}
+
+/// Format a time in ms since the epoch as local `HH:MM:SS`
+pub fn format_time(time_ms: u64) -> String {
+    let secs = (time_ms / 1000) as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return "??:??:??".to_string();
+    }
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
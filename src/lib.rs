@@ -0,0 +1,32 @@
+//! Drive interactive CLI sessions (REPLs, shells, ...) running under teetty.
+//!
+//! Each session lives in a directory under [`session::base_dir`] holding a
+//! stdin FIFO, the captured stdout file, metadata and a control socket. The
+//! [`Session`] type is the high-level API; the modules expose the building
+//! blocks the `via` CLI is made of.
+
+pub mod attach;
+pub mod budget;
+pub mod control;
+mod error;
+pub mod export;
+pub mod fifo;
+pub mod hooks;
+pub mod index;
+pub mod lockstep;
+pub mod mcp;
+pub mod prompt;
+pub mod record;
+pub mod search;
+pub mod sentinel;
+pub mod session;
+pub mod shell;
+pub mod stanza;
+pub mod supervisor;
+pub mod tail;
+pub mod tombstone;
+
+pub use error::{Error, Result};
+pub use session::{Output, Session, SessionInfo};
+pub use supervisor::LaunchOptions;
+pub use tail::Completion;
@@ -0,0 +1,177 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::path::Path;
+
+use crate::prompt;
+use crate::record;
+use crate::session::{self, Output, Session};
+use crate::tail::Completion;
+
+// Lockstep execution: instead of writing a whole script into the session at
+// once, send one logical command at a time and wait for its response before
+// the next. Each command's output is captured on its own, and the run stops at
+// the first command whose output matches one of the session's error patterns
+// (or, with shell integration, that exits with a non-zero status).
+//
+// A logical command is one line of the script, except that a line ending in
+// `\` is sent together with the next one (as written, so the program sees the
+// continuation), a ghci `:{` ... `:}` block is sent as a whole, and so is a
+// block whose body is indented (a python `def` or `for`, a node function), up to
+// the line that closes it. Such a block is followed by a blank line when it ends
+// indented, as python's REPL needs to run it. Other blank lines are skipped,
+// including those inside a block.
+
+/// Error patterns for common programs, by program name
+const PRESETS: &[(&[&str], &[&str])] = &[
+    (&["python", "python3", "ipython", "ipython3"], &[r"^Traceback \(most recent call last\)", r"^\w+Error: "]),
+    (&["ghci", "cabal", "stack"], &[r"^<interactive>:\d+:\d+: error", r":\d+:\d+: error"]),
+    (&["node", "deno"], &[r"^Uncaught \w*Error"]),
+    (&["irb", "ruby"], &[r"\(\w+Error\)$"]),
+    (&["bash", "sh", "zsh", "dash", "ksh"], &[r": command not found$", r": No such file or directory$"]),
+    (&["nix"], &[r"^error:"]),
+    (&["psql"], &[r"^ERROR: "]),
+    (&["sqlite3"], &[r"^(Parse|Runtime) error"]),
+];
+
+/// Split a script into the commands to send one at a time
+pub fn commands(script: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut in_block = false;
+
+    for line in script.lines() {
+        if in_block {
+            current.push(line);
+            if line.trim() == ":}" {
+                in_block = false;
+                commands.push(current.join("\n"));
+                current.clear();
+            }
+            continue;
+        }
+
+        if current.last().is_some_and(|last| last.ends_with('\\')) {
+            current.push(line);
+            continue;
+        }
+        // A blank line inside an indented block doesn't end it: the next line says
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !current.is_empty() && continues_block(line) {
+            current.push(line);
+            continue;
+        }
+
+        finish_command(&mut commands, &mut current);
+        in_block = line.trim() == ":{";
+        current.push(line);
+    }
+
+    // An unfinished continuation or block is still sent
+    finish_command(&mut commands, &mut current);
+    commands
+}
+
+/// Whether `line` carries on the block started above it rather than starting a
+/// command of its own: it is indented, closes a bracket, or is a clause like
+/// python's `else:` or a shell's `fi` (but not a variable named `end`)
+fn continues_block(line: &str) -> bool {
+    const CLAUSES: &[&str] = &["else", "elif", "except", "finally", "fi", "done", "esac", "end"];
+    let trimmed = line.trim_start();
+    let word_end = trimmed.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(trimmed.len());
+    let (word, rest) = trimmed.split_at(word_end);
+    trimmed.len() < line.len()
+        || trimmed.starts_with(['}', ')', ']'])
+        || (CLAUSES.contains(&word) && !rest.trim_start().starts_with(['=', '(', '.']))
+}
+
+/// Add the lines of the command in `current` to `commands` as one input. A block
+/// that ends indented (a python `def`, say) gets a blank line after it, which is
+/// what ends the block at the REPL's continuation prompt.
+fn finish_command(commands: &mut Vec<String>, current: &mut Vec<&str>) {
+    let Some(last) = current.last() else { return };
+    let mut command = current.join("\n");
+    if current.len() > 1 && last.starts_with(char::is_whitespace) {
+        command.push_str("\n\n");
+    }
+    commands.push(command);
+    current.clear();
+}
+
+/// The preset error patterns for a command, from the basename of its program
+pub fn preset(command: &[String]) -> Option<&'static [&'static str]> {
+    let program = command.first()?;
+    let name = Path::new(program).file_name()?.to_str()?;
+    PRESETS.iter()
+        .find(|(names, _)| names.contains(&name))
+        .map(|(_, patterns)| *patterns)
+}
+
+/// Compile error patterns, naming the one that is invalid
+pub fn compile(patterns: &[impl AsRef<str>]) -> Result<Vec<Regex>> {
+    patterns.iter()
+        .map(|p| Regex::new(p.as_ref()).with_context(|| format!("invalid error pattern: {}", p.as_ref())))
+        .collect()
+}
+
+/// A session's error patterns: those stored with `run --error-pattern`, else
+/// the preset for the program it runs
+pub fn error_patterns(session: &str) -> Result<Vec<Regex>> {
+    let dir = session::session_path(session)?;
+    if let Ok(text) = std::fs::read_to_string(dir.join("errors")) {
+        let patterns: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+        return compile(&patterns);
+    }
+
+    let command: Vec<String> = std::fs::read_to_string(dir.join("command"))
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(String::from)
+        .collect();
+    compile(preset(&command).unwrap_or(&[]))
+}
+
+/// Why a lockstep run stopped early
+#[derive(Debug, Clone)]
+pub struct Failure {
+    /// Index of the failing command
+    pub index: usize,
+    pub reason: String,
+}
+
+/// The first line of a command's output (without the echo of the command)
+/// that matches an error pattern
+fn find_error(output: &str, input: &str, patterns: &[Regex]) -> Option<String> {
+    let output = record::strip_echo(&output.replace("\r\n", "\n"), input);
+    output.lines()
+        .map(|line| prompt::strip_ansi(line.as_bytes()))
+        .find(|line| patterns.iter().any(|p| p.is_match(line)))
+        .map(|line| format!("matched error pattern: {}", line.trim()))
+}
+
+/// Send `commands` one at a time, waiting for `completion` after each, and
+/// hand each command's output to `on_output` as it completes. Returns the
+/// command that failed, if any; the commands after it are not sent.
+pub fn run(
+    session: &Session,
+    commands: &[String],
+    completion: &Completion,
+    timeout_secs: f64,
+    patterns: &[Regex],
+    mut on_output: impl FnMut(usize, &str, &Output) -> Result<()>,
+) -> Result<Option<Failure>> {
+    for (index, input) in commands.iter().enumerate() {
+        let output = session.send_and_wait_until(input, completion, timeout_secs)?;
+        on_output(index, input, &output)?;
+
+        let reason = match output.status {
+            Some(status) if status != 0 => Some(format!("exit status {}", status)),
+            _ => find_error(&output.text, input, patterns),
+        };
+        if let Some(reason) = reason {
+            return Ok(Some(Failure { index, reason }));
+        }
+    }
+    Ok(None)
+}
@@ -1,12 +1,11 @@
 use anyhow::{Context, Result};
 use std::env;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::ops::Range;
 use std::process::exit;
 
-mod session;
-mod fifo;
-mod tail;
-mod prompt;
+use via::budget::{self, Budget};
+use via::{attach, export, fifo, index, lockstep, prompt, record, search, session, stanza, supervisor, tail, tombstone, Completion, Error, LaunchOptions, Session};
 
 fn main() {
     exit(match run() {
@@ -41,13 +40,13 @@ fn run() -> Result<i32> {
 
     if args.is_empty() {
         // via → list sessions (table format)
-        session::list_sessions(false)?;
+        cmd_list(false)?;
         return Ok(0);
     }
 
     // Check for --simple flag before other processing
     if args[0] == "--simple" {
-        session::list_sessions(true)?;
+        cmd_list(true)?;
         return Ok(0);
     }
 
@@ -59,37 +58,83 @@ fn run() -> Result<i32> {
         return Ok(0);
     }
 
+    if first_arg == "--each" {
+        // via --each PATTERN [--delim D | --idle MS] [--timeout N] line... → broadcast
+        return cmd_each(&args[1..]);
+    }
+
+    if first_arg == "pipe" {
+        // via pipe <src> <dst> [--regex RE] → feed src's last output to dst
+        cmd_pipe(&args[1..])?;
+        return Ok(0);
+    }
+
+    if first_arg == "log" {
+        // via log [--since DURATION] [PATTERN] → recent output of all sessions by time
+        cmd_log(&args[1..])?;
+        return Ok(0);
+    }
+
+    if first_arg == "grep" {
+        // via grep [-i] [-C N] PATTERN [SESSIONS] → search the output of all sessions
+        return cmd_grep(None, &args[1..]);
+    }
+
+    if first_arg == "gc" {
+        // via gc [PATTERN] → remove the tombstones of ended sessions
+        cmd_gc(&args[1..])?;
+        return Ok(0);
+    }
+
+    if first_arg == "replay" {
+        // via replay FILE [--speed N] → act as the REPL captured by `record`
+        cmd_replay(&args[1..])?;
+        return Ok(0);
+    }
+
+    if first_arg == "__supervise" {
+        // via __supervise <session> [flags] -- <cmd> ... → launch a claimed session
+        // in the background (how the library starts sessions without forking)
+        cmd_supervise(&args[1..])?;
+        return Ok(0);
+    }
+
+    if first_arg == "mcp" {
+        // via mcp → Model Context Protocol server on stdio
+        via::mcp::serve(std::io::stdin().lock(), std::io::stdout().lock())?;
+        return Ok(0);
+    }
+
     // via run [--delim D] -- <cmd> ... → auto-generate session name
     if first_arg == "run" {
-        let remaining = &args[1..];
-        let session_name = generate_session_name(remaining)?;
-        eprintln!("[via] session: {}", session_name);
-        cmd_run(&session_name, remaining)?;
-        return Ok(0);
+        return cmd_run(None, &args[1..]);
     }
 
-    // First arg is the session name. `.` resolves to the current directory's
-    // name, so `via . <command>` always targets the session for this checkout.
-    let session_name = resolve_session_name(first_arg.clone())?;
+    // First arg is the session name. `.` resolves to a name for the current
+    // directory, so `via . <command>` always targets the session for this checkout.
+    let session_name = session::resolve_name(first_arg)?;
 
     if args.len() < 2 {
         // via <session> with no args — use shorthand with piped stdin
-        cmd_shorthand(&session_name, &[])?;
-        return Ok(0);
+        return cmd_shorthand(&session_name, &[]);
     }
 
     let subcmd = &args[1];
     let remaining_args = &args[2..];
 
+    // An ended session only answers to status, path and the commands that read
+    // its transcript; run reclaims its name
+    if !matches!(subcmd.as_str(), "help" | "run" | "status" | "path"
+                 | "tail" | "output" | "grep" | "stanzas" | "export" | "record") {
+        tombstone::check_live(&session_name)?;
+    }
+
     match subcmd.as_str() {
         "help" => {
             show_session_usage(&session_name);
             Ok(0)
         }
-        "run" => {
-            cmd_run(&session_name, remaining_args)?;
-            Ok(0)
-        }
+        "run" => cmd_run(Some(&session_name), remaining_args),
         "wait" => {
             cmd_wait(&session_name, remaining_args)?;
             Ok(0)
@@ -106,6 +151,36 @@ fn run() -> Result<i32> {
             cmd_path(&session_name)?;
             Ok(0)
         }
+        "status" => {
+            cmd_status(&session_name)?;
+            Ok(0)
+        }
+        "export" => {
+            cmd_export(&session_name, remaining_args)?;
+            Ok(0)
+        }
+        "grep" => cmd_grep(Some(&session_name), remaining_args),
+        "detect-delim" => {
+            cmd_detect_delim(&session_name, remaining_args)?;
+            Ok(0)
+        }
+        "output" => {
+            cmd_output(&session_name, remaining_args)?;
+            Ok(0)
+        }
+        "source" => cmd_source(&session_name, remaining_args),
+        "attach" => {
+            cmd_attach(&session_name, remaining_args)?;
+            Ok(0)
+        }
+        "stanzas" => {
+            cmd_stanzas(&session_name, remaining_args)?;
+            Ok(0)
+        }
+        "record" => {
+            cmd_record(&session_name, remaining_args)?;
+            Ok(0)
+        }
         _ => {
             // Only try shorthand if the session actually exists.
             // Otherwise it's likely a typo in the subcommand.
@@ -113,26 +188,11 @@ fn run() -> Result<i32> {
             if !dir.exists() {
                 anyhow::bail!("unknown session '{}' (try: via help)", session_name);
             }
-            cmd_shorthand(&session_name, &args[1..])?;
-            Ok(0)
+            cmd_shorthand(&session_name, &args[1..])
         }
     }
 }
 
-/// Resolve a session name, mapping `.` to the current directory's name.
-/// Everything else is returned unchanged. This keeps `via` git-agnostic: the
-/// "session here" is simply the basename of the working directory.
-fn resolve_session_name(name: String) -> Result<String> {
-    if name == "." {
-        let cwd = env::current_dir()?;
-        cwd.file_name()
-            .map(|s| s.to_string_lossy().into_owned())
-            .ok_or_else(|| anyhow::anyhow!("cannot resolve '.': cwd has no basename"))
-    } else {
-        Ok(name)
-    }
-}
-
 fn show_usage_global() {
     println!(r#"usage:
   via [--simple]                                          # list sessions (tabular format by default)
@@ -141,77 +201,266 @@ fn show_usage_global() {
   via . <command>                                         # '.' names the session after the current directory
   via run [--delim 'PROMPT>'] [--bg] -- <cmd> ...         # start session with auto-generated name
   via <session> run [--delim 'PROMPT>'] [--bg] -- <cmd>   # start a named session running <cmd>
+      [--group G]                                         #   add the session to group G
+      [--idle MS]                                         #   store MS of silence as the default completion
+      [--detect-delim]                                    #   probe the prompt at startup and store it
+      [--shell]                                           #   bash/zsh/fish: delimit output by OSC 133 marks
+      [--sentinel [TEMPLATE]]                             #   end each response at a marker echoed after it
+      [--error-pattern RE]                                #   mark failed commands in lockstep runs
+      [--restart on-failure|always]                       #   relaunch the command when it exits
+      [--max-restarts N] [--backoff MS]                   #   give up after N; wait MS, doubling each time
+      [--rm]                                              #   remove the session when it ends, no tombstone
+      [--hook EVENT=COMMAND]                              #   run COMMAND on start, ready, exit or match:RE
+      [--cwd DIR]                                         #   run the command in DIR
+      [--env KEY=VALUE] [--env-file FILE] [--clear-env]   #   set variables; start from an empty environment
   via <session> wait [--until 'PROMPT>'] [--timeout N]    # wait for prompt (default: stored delim)
+      [--idle MS]                                         #   or for MS milliseconds of silence
   via <session> [--delim 'PROMPT>'] [--timeout N] line    # write input and stream until delim
+      [--idle MS]                                         #   or until silent for MS milliseconds
+      [--max-lines N] [--max-bytes N]                     #   elide the middle of longer output
+  via <session> --lockstep < script                       # send one command per prompt, stop at an error
+  via <session> detect-delim [--store]                    # find the prompt by probing with an empty line
+  via <session> status                                    # running, or how it ended and its last output
+  via --each 'PATTERN' [--idle MS] [--timeout N] line     # send to every matching session (glob or @group)
+  via pipe <src> <dst> [--regex RE]                       # send src's last output (or a capture) to dst
+  via log [--since DURATION] [PATTERN]                    # recent output of all sessions, by time
+  via grep [-i] [-C N] REGEX [PATTERN]                    # search the output of all sessions
+  via gc [PATTERN]                                        # remove ended sessions (all, glob or @group)
+  via replay FILE [--speed N]                             # act as the REPL saved by 'record'
+  via mcp                                                 # serve sessions as MCP tools over stdio
 
 low-level usage:
   via <session> write [line...]                           # write (reads stdin if none)
   via <session> tail -n N                                 # tail last N lines
   via <session> tail -f [-n N]                            # follow output in real-time
+  via <session> tail -f --timestamps [-n N]               # follow, prefixing lines with the time written
+  via <session> tail -f --until ['PROMPT>'] [--timeout N] # follow until the next prompt (bare = stored delim)
+  via <session> tail -f|-n N --grep REGEX [--plain]       # only lines matching REGEX; --plain strips ANSI
   via <session> tail --since ['PROMPT>']                  # tail since last prompt (bare = stored delim)
   via <session> tail --delim ['PROMPT>']                  # last stanza (bare = stored delim)
+  via <session> tail --stanza N [--delim 'PROMPT>']       # stanza N from 'stanzas' (-1 = last)
+  via <session> tail ... [--max-lines N] [--max-bytes N]  # elide the middle of longer output
   via <session> tail --until ['PROMPT>'] [--timeout N]    # stream until prompt (bare = stored delim)
+  via <session> tail --idle MS [--timeout N]              # stream until silent for MS milliseconds
   via <session> tail --since --until [--timeout N]        # stream from last prompt until next
-  via <session> path                                      # show session path"#);
+  via <session> tail --from-offset N [--json]             # output from byte offset N (new offset on stderr)
+  via <session> tail --cursor NAME [--json]               # output since named cursor, then advance it
+  via <session> tail --since-time DURATION [--json]       # output written in the last DURATION (e.g. 5m)
+  via <session> path                                      # show session path
+  via <session> source FILE [--error-pattern RE]          # run a script in lockstep, stop at an error
+  via <session> attach [--detach-key KEY]                 # take over from this terminal (detach: Ctrl-])
+  via <session> output OFFSET [LEN]                       # raw output by byte range (e.g. an elided part)
+  via <session> stanzas [--delim 'PROMPT>']               # list exchanges with their input and size
+  via <session> grep [-i] [-C N] REGEX                    # search output, annotated by stanza and input
+  via <session> export --format asciicast|markdown|html   # write a transcript of the session
+      [-o FILE] [--delim 'PROMPT>']                       #   to FILE instead of stdout
+  via <session> record [-o FILE]                          # save exchanges as a replayable fixture"#);
 }
 
 fn show_session_usage(session: &str) {
     println!(r#"usage for '{session}':
   via {session} help                                      # help for a specific session
   via {session} wait [--until 'PROMPT>'] [--timeout N]    # wait for prompt (default: stored delim)
+      [--idle MS]                                         #   or for MS milliseconds of silence
   via {session} [--delim 'PROMPT>'] [--timeout N] line    # write input and stream until delim
+      [--idle MS]                                         #   or until silent for MS milliseconds
+      [--max-lines N] [--max-bytes N]                     #   elide the middle of longer output
+  via {session} --lockstep < script                       # send one command per prompt, stop at an error
+  via {session} detect-delim [--store]                    # find the prompt by probing with an empty line
+  via {session} status                                    # running, or how it ended and its last output
 
 low-level usage:
   via {session} write [line...]                           # write (reads stdin if none)
   via {session} tail -n N                                 # tail last N lines
   via {session} tail -f [-n N]                            # follow output in real-time
+  via {session} tail -f --timestamps [-n N]               # follow, prefixing lines with the time written
+  via {session} tail -f --until ['PROMPT>'] [--timeout N] # follow until the next prompt (bare = stored delim)
+  via {session} tail -f|-n N --grep REGEX [--plain]       # only lines matching REGEX; --plain strips ANSI
   via {session} tail --since ['PROMPT>']                  # tail since last prompt (bare = stored delim)
   via {session} tail --delim ['PROMPT>']                  # last stanza (bare = stored delim)
+  via {session} tail --stanza N [--delim 'PROMPT>']       # stanza N from 'stanzas' (-1 = last)
+  via {session} tail ... [--max-lines N] [--max-bytes N]  # elide the middle of longer output
   via {session} tail --until ['PROMPT>'] [--timeout N]    # stream until prompt (bare = stored delim)
-  via {session} path                                      # show session path"#);
-}
-
-/// Generate a session name like "cabal-00", "cabal-01" based on the command.
-fn generate_session_name(args: &[String]) -> Result<String> {
-    let cmd_name = args.iter()
-        .position(|a| a == "--")
-        .and_then(|pos| args.get(pos + 1))
-        .map(|cmd| {
-            std::path::Path::new(cmd)
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("session")
-                .to_string()
-        })
-        .unwrap_or_else(|| "session".to_string());
+  via {session} tail --idle MS [--timeout N]              # stream until silent for MS milliseconds
+  via {session} tail --from-offset N [--json]             # output from byte offset N (new offset on stderr)
+  via {session} tail --cursor NAME [--json]               # output since named cursor, then advance it
+  via {session} tail --since-time DURATION [--json]       # output written in the last DURATION (e.g. 5m)
+  via {session} path                                      # show session path
+  via {session} source FILE [--error-pattern RE]          # run a script in lockstep, stop at an error
+  via {session} attach [--detach-key KEY]                 # take over from this terminal (detach: Ctrl-])
+  via {session} output OFFSET [LEN]                       # raw output by byte range (e.g. an elided part)
+  via {session} stanzas [--delim 'PROMPT>']               # list exchanges with their input and size
+  via {session} grep [-i] [-C N] REGEX                    # search output, annotated by stanza and input
+  via {session} export --format asciicast|markdown|html   # write a transcript of the session
+      [-o FILE] [--delim 'PROMPT>']                       #   to FILE instead of stdout
+  via {session} record [-o FILE]                          # save exchanges as a replayable fixture"#);
+}
+
+/// Print the session listing, as a table or just the names
+fn cmd_list(simple: bool) -> Result<()> {
+    let sessions = Session::list()?;
 
-    let base = session::base_dir()?;
-    for index in 0u32..100 {
-        let name = format!("{}-{:02}", cmd_name, index);
-        if !base.join(&name).exists() {
-            return Ok(name);
+    if simple {
+        // Simple format: just session names
+        for info in sessions {
+            println!("{}", info.name);
         }
+        return Ok(());
+    }
+
+    // Table format
+    if sessions.is_empty() {
+        return Ok(());
+    }
+
+    // Show at most 20 characters of the prompt line
+    let prompts: Vec<String> = sessions.iter()
+        .map(|info| match info.prompt.as_deref() {
+            Some(p) if p.chars().count() > 20 => format!("{}...", p.chars().take(20).collect::<String>()),
+            Some(p) => p.to_string(),
+            None => String::new(),
+        })
+        .collect();
+
+    let states: Vec<String> = sessions.iter()
+        .map(|info| match info.ended {
+            Some(ref tombstone) => tombstone.state(),
+            None => "running".to_string(),
+        })
+        .collect();
+
+    // Calculate column widths
+    let max_state_len = states.iter()
+        .map(|s| s.len())
+        .max()
+        .unwrap_or(5)
+        .max(5); // "State" header
+
+    let max_session_len = sessions.iter()
+        .map(|info| info.name.len())
+        .max()
+        .unwrap_or(7)
+        .max(7); // "Session" header
+
+    let max_prompt_len = prompts.iter()
+        .map(|p| p.len())
+        .max()
+        .unwrap_or(11)
+        .max(11); // "Prompt Line" header
+
+    let max_cwd_len = sessions.iter()
+        .map(|info| info.cwd.as_ref().map(|s| s.len()).unwrap_or(0))
+        .max()
+        .unwrap_or(17)
+        .max(17); // "Working Directory" header
+
+    // Print header
+    println!("{:<width_session$}  {:<width_state$}  {:<width_prompt$}  {:<width_cwd$}  Command",
+             "Session", "State", "Prompt Line", "Working Directory",
+             width_session = max_session_len,
+             width_state = max_state_len,
+             width_prompt = max_prompt_len,
+             width_cwd = max_cwd_len);
+
+    // Print sessions
+    for ((info, state), prompt_str) in sessions.iter().zip(&states).zip(&prompts) {
+        println!("{:<width_session$}  {:<width_state$}  {:<width_prompt$}  {:<width_cwd$}  {}",
+                 info.name, state, prompt_str,
+                 info.cwd.as_deref().unwrap_or(""),
+                 info.command.as_deref().unwrap_or(""),
+                 width_session = max_session_len,
+                 width_state = max_state_len,
+                 width_prompt = max_prompt_len,
+                 width_cwd = max_cwd_len);
     }
-    anyhow::bail!("too many sessions for '{}'", cmd_name)
+
+    Ok(())
 }
 
-fn cmd_run(session: &str, args: &[String]) -> Result<()> {
+/// Start a session, claiming `session` or a name generated from the command
+fn cmd_run(session: Option<&str>, args: &[String]) -> Result<i32> {
     // Parse flags before the "--" separator
+    let usage = format!("usage: via {} run [--delim DELIM] -- <command> [args...]", session.unwrap_or("[<session>]"));
     let separator_pos = args.iter().position(|a| a == "--");
     match separator_pos {
-        None => anyhow::bail!("usage: via {} run [--delim DELIM] -- <command> [args...]", session),
-        Some(pos) if pos + 1 >= args.len() => {
-            anyhow::bail!("usage: via {} run [--delim DELIM] -- <command> [args...]", session)
-        }
+        None => anyhow::bail!("{}", usage),
+        Some(pos) if pos + 1 >= args.len() => anyhow::bail!("{}", usage),
         _ => {}
     }
     let separator_pos = separator_pos.unwrap();
     let pre_args = &args[..separator_pos];
     let cmd_args = &args[separator_pos + 1..];
 
-    // Parse flags from pre-separator args
-    let mut delim: Option<String> = None;
+    let (options, background, detect_delim) = launch_flags(pre_args, cmd_args)?;
+
+    // Claim the session name before anything else can take it
+    let session = match session {
+        Some(name) => {
+            session::claim(name)?;
+            name.to_string()
+        }
+        None => {
+            let name = session::claim_generated(cmd_args)?;
+            eprintln!("[via] session: {}", name);
+            name
+        }
+    };
+    let session = session.as_str();
+
+    // Print session info
+    eprintln!("[via] dir: {}", session::session_path(session)?.display());
+    eprintln!("[via] stdin: {}", session::stdin_path(session)?.display());
+    eprintln!("[via] stdout: {}", session::stdout_path(session)?.display());
+    eprintln!("[via] launching: {}", cmd_args.join(" "));
+
+    if background {
+        supervisor::spawn_forked(session, cmd_args, &options)?;
+        if detect_delim {
+            // The session is up either way; a failed detection only leaves it without a delim
+            match prompt::detect_delim(session, DETECT_IDLE_MS, tail::DEFAULT_TIMEOUT) {
+                Ok(delim) => {
+                    session::set_delim(session, &delim)?;
+                    eprintln!("[via] detected delim: '{}'", delim);
+                }
+                Err(err) => eprintln!("[via] could not detect delim: {:#}", err),
+            }
+        }
+        Ok(0)
+    } else {
+        if detect_delim {
+            // Detect in the background while the session runs in the foreground
+            let session = session.to_string();
+            std::thread::spawn(move || {
+                if let Ok(delim) = prompt::detect_delim(&session, DETECT_IDLE_MS, tail::DEFAULT_TIMEOUT) {
+                    let _ = session::set_delim(&session, &delim);
+                }
+            });
+        }
+        supervisor::run(session, cmd_args, &options)
+    }
+}
+
+/// Supervise: via __supervise <session> [flags] -- <command> [args...]
+/// Launches an already claimed session the way `run --bg` does. Hidden: this is
+/// what `Session::spawn` runs so the embedding process is never forked.
+fn cmd_supervise(args: &[String]) -> Result<()> {
+    let usage = "usage: via __supervise <session> [flags] -- <command> [args...]";
+    let (session, args) = args.split_first().ok_or_else(|| anyhow::anyhow!("{}", usage))?;
+    let separator_pos = args.iter().position(|a| a == "--")
+        .filter(|&pos| pos + 1 < args.len())
+        .ok_or_else(|| anyhow::anyhow!("{}", usage))?;
+    let cmd_args = &args[separator_pos + 1..];
+    let (options, _, _) = launch_flags(&args[..separator_pos], cmd_args)?;
+    supervisor::spawn_forked(session, cmd_args, &options)
+}
+
+/// Parse the `run` flags given before `--`, returning the launch options and
+/// whether `--bg` and `--detect-delim` were given
+fn launch_flags(pre_args: &[String], cmd_args: &[String]) -> Result<(LaunchOptions, bool, bool)> {
+    let mut options = LaunchOptions::default();
     let mut background = false;
+    let mut detect_delim = false;
+    let mut sentinel: Option<Option<String>> = None;
     {
         let mut i = 0;
         while i < pre_args.len() {
@@ -220,128 +469,187 @@ fn cmd_run(session: &str, args: &[String]) -> Result<()> {
                     if i + 1 >= pre_args.len() {
                         anyhow::bail!("--delim requires a value");
                     }
-                    delim = Some(pre_args[i + 1].clone());
+                    options.delim = Some(pre_args[i + 1].clone());
                     i += 2;
                 }
                 "--background" | "--bg" => {
                     background = true;
                     i += 1;
                 }
+                "--rm" => {
+                    options.remove = true;
+                    i += 1;
+                }
+                "--cwd" => {
+                    let dir = pre_args.get(i + 1)
+                        .ok_or_else(|| anyhow::anyhow!("--cwd requires a directory"))?;
+                    let dir = std::fs::canonicalize(dir)
+                        .with_context(|| format!("invalid --cwd: {}", dir))?;
+                    if !dir.is_dir() {
+                        anyhow::bail!("--cwd {} is not a directory", dir.display());
+                    }
+                    options.cwd = Some(dir);
+                    i += 2;
+                }
+                "--env" => {
+                    let spec = pre_args.get(i + 1)
+                        .ok_or_else(|| anyhow::anyhow!("--env requires KEY=VALUE"))?;
+                    options.env.push(supervisor::parse_env(spec)?);
+                    i += 2;
+                }
+                "--env-file" => {
+                    let path = pre_args.get(i + 1)
+                        .ok_or_else(|| anyhow::anyhow!("--env-file requires a file"))?;
+                    options.env.extend(supervisor::read_env_file(std::path::Path::new(path))?);
+                    i += 2;
+                }
+                "--clear-env" => {
+                    options.clear_env = true;
+                    i += 1;
+                }
+                "--hook" => {
+                    let spec = pre_args.get(i + 1)
+                        .ok_or_else(|| anyhow::anyhow!("--hook requires EVENT=COMMAND"))?;
+                    via::hooks::parse(spec)?;
+                    options.hooks.push(spec.clone());
+                    i += 2;
+                }
+                "--group" => {
+                    if i + 1 >= pre_args.len() {
+                        anyhow::bail!("--group requires a name");
+                    }
+                    options.group = Some(pre_args[i + 1].clone());
+                    i += 2;
+                }
+                "--idle" => {
+                    options.idle = Some(idle_arg(pre_args, i)?);
+                    i += 2;
+                }
+                "--detect-delim" => {
+                    detect_delim = true;
+                    i += 1;
+                }
+                "--shell" => {
+                    options.shell = true;
+                    i += 1;
+                }
+                "--error-pattern" => {
+                    if i + 1 >= pre_args.len() {
+                        anyhow::bail!("--error-pattern requires a regex");
+                    }
+                    options.errors.push(pre_args[i + 1].clone());
+                    i += 2;
+                }
+                "--restart" => {
+                    let policy = pre_args.get(i + 1)
+                        .ok_or_else(|| anyhow::anyhow!("--restart requires on-failure or always"))?;
+                    options.restart = supervisor::Restart::parse(policy)?;
+                    i += 2;
+                }
+                "--max-restarts" => {
+                    let value = pre_args.get(i + 1)
+                        .ok_or_else(|| anyhow::anyhow!("--max-restarts requires a number"))?;
+                    options.max_restarts = Some(value.parse::<u32>()
+                        .with_context(|| format!("invalid --max-restarts: {} (use 0 to {})", value, u32::MAX))?);
+                    i += 2;
+                }
+                "--backoff" => {
+                    let value = pre_args.get(i + 1)
+                        .ok_or_else(|| anyhow::anyhow!("--backoff requires a number of milliseconds"))?;
+                    options.backoff = Some(value.parse().with_context(|| format!("invalid backoff: {}", value))?);
+                    i += 2;
+                }
+                "--sentinel" => {
+                    // The template is optional; without one the command's preset is used
+                    match pre_args.get(i + 1).filter(|a| !a.starts_with("--")) {
+                        Some(template) => {
+                            sentinel = Some(Some(template.clone()));
+                            i += 2;
+                        }
+                        None => {
+                            sentinel = Some(None);
+                            i += 1;
+                        }
+                    }
+                }
                 _ => anyhow::bail!("unknown flag before '--': {}", pre_args[i]),
             }
         }
     }
-
-    // Get session directory and create it
-    let dir = session::session_path(session)?;
-    std::fs::create_dir_all(&dir)
-        .with_context(|| format!("failed to create directory {}", dir.display()))?;
-
-    // Write metadata files
-    let command = cmd_args.join(" ");
-    std::fs::write(dir.join("command"), &command)
-        .with_context(|| "failed to write command metadata")?;
-
-    if let Some(ref d) = delim {
-        std::fs::write(dir.join("delim"), d)
-            .with_context(|| "failed to write delim metadata")?;
+    if detect_delim && options.delim.is_some() {
+        anyhow::bail!("--detect-delim cannot be combined with --delim");
     }
-
-    if let Ok(cwd) = env::current_dir() {
-        std::fs::write(dir.join("cwd"), cwd.to_string_lossy().as_bytes())
-            .with_context(|| "failed to write cwd metadata")?;
+    if options.restart == supervisor::Restart::Never && (options.max_restarts.is_some() || options.backoff.is_some()) {
+        anyhow::bail!("--max-restarts and --backoff need --restart on-failure|always");
     }
+    if options.shell && via::shell::Shell::from_command(cmd_args).is_none() {
+        anyhow::bail!("--shell supports bash, zsh and fish, not '{}'", cmd_args[0]);
+    }
+    lockstep::compile(&options.errors)?;
+    if let Some(template) = sentinel {
+        let template = match template {
+            Some(t) => t,
+            None => via::sentinel::preset(cmd_args)
+                .ok_or_else(|| anyhow::anyhow!(
+                    "no sentinel preset for '{}' (use --sentinel TEMPLATE with {})",
+                    cmd_args[0], via::sentinel::PLACEHOLDER))?
+                .to_string(),
+        };
+        via::sentinel::validate(&template)?;
+        options.sentinel = Some(template);
+    }
+    Ok((options, background, detect_delim))
+}
 
-    let stdin_path = dir.join("stdin");
-    let stdout_path = dir.join("stdout");
-
-    // Print session info
-    eprintln!("[via] dir: {}", dir.display());
-    eprintln!("[via] stdin: {}", stdin_path.display());
-    eprintln!("[via] stdout: {}", stdout_path.display());
-    eprintln!("[via] launching: {}", command);
-
-    // Build teetty command
-    let mut cmd = std::process::Command::new("teetty");
-    cmd.arg("-i").arg(&stdin_path)
-       .arg("-o").arg(&stdout_path)
-       .arg("--truncate")
-       .arg("--");
+/// Silence that marks startup or a probe's response as settled when detecting a delim
+const DETECT_IDLE_MS: u64 = 500;
 
-    // Add the command and args
-    for arg in cmd_args {
-        cmd.arg(arg);
-    }
+/// Detect: via <session> detect-delim [--store] [--idle MS] [--timeout N]
+/// Prints the detected prompt; --store also saves it as the session's delim.
+fn cmd_detect_delim(session: &str, args: &[String]) -> Result<()> {
+    let mut store = false;
+    let mut idle = DETECT_IDLE_MS;
+    let mut timeout = tail::DEFAULT_TIMEOUT;
+    let mut i = 0;
 
-    if background {
-        // Detach teetty from our stdio so it doesn't consume the shell's
-        // stdin or pollute stdout. teetty uses the FIFO and file instead.
-        cmd.stdin(std::process::Stdio::null());
-        cmd.stdout(std::process::Stdio::null());
-        cmd.stderr(std::process::Stdio::null());
-
-        // Fork before spawning teetty so the child process is teetty's
-        // actual parent and can wait on it for cleanup.
-        unsafe {
-            match libc::fork() {
-                -1 => anyhow::bail!("fork failed"),
-                0 => {
-                    // Child: detach from terminal, spawn teetty, wait, clean up
-                    libc::setsid();
-                    let mut child = match cmd.spawn() {
-                        Ok(c) => c,
-                        Err(_) => std::process::exit(1),
-                    };
-                    let _ = child.wait();
-                    let _ = std::fs::remove_dir_all(&dir);
-                    std::process::exit(0);
-                }
-                _ => {}
+    while i < args.len() {
+        match args[i].as_str() {
+            "--store" => {
+                store = true;
+                i += 1;
             }
-        }
-
-        // Parent: poll until teetty has created the stdin FIFO and stdout file
-        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
-        loop {
-            if stdin_path.exists() && stdout_path.exists() {
-                break;
+            "--idle" => {
+                idle = idle_arg(args, i)?;
+                i += 2;
             }
-            if std::time::Instant::now() > deadline {
-                anyhow::bail!("teetty did not create stdin/stdout within 5s");
+            "--timeout" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--timeout requires a number");
+                }
+                timeout = args[i + 1].parse()
+                    .with_context(|| format!("invalid timeout: {}", args[i + 1]))?;
+                i += 2;
             }
-            std::thread::sleep(std::time::Duration::from_millis(10));
+            other => anyhow::bail!("unexpected argument: {}", other),
         }
+    }
 
-        Ok(())
-    } else {
-        // Set up cleanup handler for Ctrl-C
-        let dir_for_cleanup = dir.clone();
-        ctrlc::set_handler(move || {
-            let _ = std::fs::remove_dir_all(&dir_for_cleanup);
-            std::process::exit(130);
-        }).ok();
-
-        // Run teetty in the foreground — blocks until the subprocess exits.
-        let status = cmd.status()
-            .with_context(|| "failed to execute teetty (is it installed?)")?;
-
-        // Cleanup directory after teetty exits
-        std::fs::remove_dir_all(&dir).ok();
-
-        if let Some(code) = status.code() {
-            std::process::exit(code);
-        } else {
-            std::process::exit(1);
-        }
+    Session::open(session)?;
+    let delim = prompt::detect_delim(session, idle, timeout)?;
+    println!("{}", delim);
+    if store {
+        session::set_delim(session, &delim)?;
+        eprintln!("[via] stored delim for '{}'", session);
     }
+    Ok(())
 }
 
 /// Wait for a prompt to appear in an already-running session's output.
 /// Thin wrapper around tail --until with suppressed output.
-/// `via <session> wait [--until PROMPT] [--timeout N]`
-/// If --until is bare or omitted, uses stored session delim.
+/// `via <session> wait [--until PROMPT | --idle MS] [--timeout N]`
+/// If --until is bare or omitted, uses stored session delim (or idle time).
 fn cmd_wait(session: &str, args: &[String]) -> Result<()> {
-    let mut prompt: Option<String> = None;
+    let mut completion: Option<Completion> = None;
     let mut timeout = tail::DEFAULT_TIMEOUT;
     let mut i = 0;
 
@@ -349,9 +657,13 @@ fn cmd_wait(session: &str, args: &[String]) -> Result<()> {
         match args[i].as_str() {
             "--until" => {
                 let (val, consumed) = session::resolve_delim(session, args, i)?;
-                prompt = Some(val);
+                completion = Some(Completion::Prompt(val));
                 i += consumed;
             }
+            "--idle" => {
+                completion = Some(Completion::Idle(idle_arg(args, i)?));
+                i += 2;
+            }
             "--timeout" => {
                 if i + 1 >= args.len() {
                     anyhow::bail!("--timeout requires a number");
@@ -366,84 +678,1228 @@ fn cmd_wait(session: &str, args: &[String]) -> Result<()> {
         }
     }
 
-    // Fall back to stored delim if no prompt specified
-    let prompt = match prompt {
-        Some(p) => p,
-        None => session::get_delim(session)?
-            .ok_or_else(|| anyhow::anyhow!("no delimiter: use --until PROMPT or set --delim on 'via run'"))?,
+    // Falls back to the stored delim (or idle time) if no prompt specified
+    let session = Session::open(session)?;
+    let completion = match completion {
+        Some(c) => c,
+        None => session::default_completion(session.name())?,
     };
-
-    tail::follow_until(session, &prompt, timeout, 0, &mut std::io::sink())?;
-    eprintln!("[via] ready (prompt detected)");
+    session.wait_until(&completion, timeout)?;
+    match completion {
+        Completion::Prompt(_) | Completion::Shell => eprintln!("[via] ready (prompt detected)"),
+        Completion::Idle(ms) => eprintln!("[via] ready (silent for {}ms)", ms),
+        Completion::Sentinel { .. } => eprintln!("[via] ready (sentinel answered)"),
+    }
     Ok(())
 }
 
+/// Parse the count after a `--max-lines` or `--max-bytes` flag at `args[i]`
+fn count_arg(args: &[String], i: usize) -> Result<usize> {
+    let value = args.get(i + 1)
+        .ok_or_else(|| anyhow::anyhow!("{} requires a number", args[i]))?;
+    value.parse().with_context(|| format!("invalid number: {}", value))
+}
+
+/// Parse the milliseconds after an `--idle` flag at `args[i]`
+fn idle_arg(args: &[String], i: usize) -> Result<u64> {
+    let value = args.get(i + 1)
+        .ok_or_else(|| anyhow::anyhow!("--idle requires a number of milliseconds"))?;
+    value.parse().with_context(|| format!("invalid idle time: {}", value))
+}
+
 fn cmd_write(session: &str, args: &[String]) -> Result<()> {
-    fifo::write_session(session, args)
+    // Lines given as arguments go through the control socket so they're
+    // serialized with other clients' input. Piped stdin is forwarded as it
+    // arrives, which suits interactive use.
+    if !args.is_empty() {
+        Session::open(session)?.send(&args.join(" "))?;
+        return Ok(());
+    }
+    fifo::forward_lines(session, std::io::stdin().lock())
 }
 
-fn cmd_tail(session: &str, args: &[String]) -> Result<()> {
-    tail::tail_session(session, args)
+#[derive(Debug)]
+enum TailMode {
+    Plain,
+    Since(String),
+    Delim(String),
 }
 
-fn cmd_path(session: &str) -> Result<()> {
-    let path = session::session_path(session)?;
-    println!("{}", path.display());
-    Ok(())
+struct TailOptions {
+    mode: TailMode,
+    lines: Option<usize>,
+    follow: bool,
+    until: Option<Completion>,
+    timeout_secs: Option<f64>,
+    from_offset: Option<u64>,
+    cursor: Option<String>,
+    since_time: Option<std::time::Duration>,
+    timestamps: bool,
+    plain: bool,
+    grep: Option<regex::Regex>,
+    stanza: Option<i64>,
+    budget: Budget,
+    json: bool,
 }
 
-/// Shorthand: via <session> [--delim D] [--timeout N] line...
-/// Uses stored delim if --delim not provided.
-fn cmd_shorthand(session: &str, args: &[String]) -> Result<()> {
-    let mut delim: Option<String> = None;
-    let mut timeout = tail::DEFAULT_TIMEOUT;
-    let mut input_args: Vec<String> = Vec::new();
+/// Parse tail arguments
+fn parse_tail_args(session: &str, args: &[String]) -> Result<TailOptions> {
+    let mut mode = TailMode::Plain;
+    let mut lines: Option<usize> = None;
+    let mut follow = false;
+    let mut until: Option<Completion> = None;
+    let mut timeout_secs: Option<f64> = None;
+    let mut from_offset: Option<u64> = None;
+    let mut cursor: Option<String> = None;
+    let mut since_time: Option<std::time::Duration> = None;
+    let mut timestamps = false;
+    let mut plain = false;
+    let mut grep: Option<regex::Regex> = None;
+    let mut stanza: Option<i64> = None;
+    let mut budget = Budget::default();
+    let mut json = false;
     let mut i = 0;
 
     while i < args.len() {
         match args[i].as_str() {
+            "-f" => {
+                follow = true;
+                i += 1;
+            }
+            "-n" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("usage: via {} tail -n <N> [--since PROMPT|--delim PROMPT|-f]", session);
+                }
+                lines = Some(args[i + 1].parse()
+                    .with_context(|| format!("invalid number: {}", args[i + 1]))?);
+                i += 2;
+            }
+            "--since" => {
+                if !matches!(mode, TailMode::Plain) {
+                    anyhow::bail!("only one of --since/--delim allowed");
+                }
+                let (val, consumed) = session::resolve_delim(session, args, i)?;
+                mode = TailMode::Since(val);
+                i += consumed;
+            }
             "--delim" => {
+                if !matches!(mode, TailMode::Plain) {
+                    anyhow::bail!("only one of --since/--delim allowed");
+                }
                 let (val, consumed) = session::resolve_delim(session, args, i)?;
-                delim = Some(val);
+                mode = TailMode::Delim(val);
+                i += consumed;
+            }
+            "--until" => {
+                if until.is_some() {
+                    anyhow::bail!("only one of --until/--idle allowed");
+                }
+                let (val, consumed) = session::resolve_delim(session, args, i)?;
+                until = Some(Completion::Prompt(val));
                 i += consumed;
             }
+            "--idle" => {
+                if until.is_some() {
+                    anyhow::bail!("only one of --until/--idle allowed");
+                }
+                until = Some(Completion::Idle(idle_arg(args, i)?));
+                i += 2;
+            }
             "--timeout" => {
                 if i + 1 >= args.len() {
                     anyhow::bail!("--timeout requires a number");
                 }
-                timeout = args[i + 1].parse()
-                    .with_context(|| format!("invalid timeout: {}", args[i + 1]))?;
+                timeout_secs = Some(args[i + 1].parse()
+                    .with_context(|| format!("invalid timeout: {}", args[i + 1]))?);
                 i += 2;
             }
-            _ => {
-                input_args.push(args[i].clone());
+            "--from-offset" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--from-offset requires a byte offset");
+                }
+                from_offset = Some(args[i + 1].parse()
+                    .with_context(|| format!("invalid offset: {}", args[i + 1]))?);
+                i += 2;
+            }
+            "--cursor" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--cursor requires a name");
+                }
+                cursor = Some(args[i + 1].clone());
+                i += 2;
+            }
+            "--since-time" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--since-time requires a duration (e.g. 5m)");
+                }
+                since_time = Some(index::parse_duration(&args[i + 1])?);
+                i += 2;
+            }
+            "--timestamps" => {
+                timestamps = true;
+                i += 1;
+            }
+            "--plain" => {
+                plain = true;
+                i += 1;
+            }
+            "--grep" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--grep requires a pattern");
+                }
+                grep = Some(regex::Regex::new(&args[i + 1])
+                    .with_context(|| format!("invalid regex: {}", args[i + 1]))?);
+                i += 2;
+            }
+            "--stanza" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--stanza requires a number (negative counts from the last)");
+                }
+                stanza = Some(args[i + 1].parse()
+                    .with_context(|| format!("invalid stanza number: {}", args[i + 1]))?);
+                i += 2;
+            }
+            "--max-lines" => {
+                budget.max_lines = Some(count_arg(args, i)?);
+                i += 2;
+            }
+            "--max-bytes" => {
+                budget.max_bytes = Some(count_arg(args, i)?);
+                i += 2;
+            }
+            "--json" => {
+                json = true;
                 i += 1;
             }
+            _ => break,
         }
     }
 
-    // Resolve delimiter
-    let prompt = match delim {
-        Some(d) => d,
-        None => session::get_delim(session)?
-            .ok_or_else(|| anyhow::anyhow!("unknown subcommand or no stored delimiter for '{}' (try: via {} help)", session, session))?,
-    };
+    // Offset-based reads are a mode of their own
+    if from_offset.is_some() || cursor.is_some() || since_time.is_some() {
+        if [from_offset.is_some(), cursor.is_some(), since_time.is_some()].iter().filter(|&&set| set).count() > 1 {
+            anyhow::bail!("only one of --from-offset/--cursor/--since-time allowed");
+        }
+        if follow || until.is_some() || lines.is_some() || !matches!(mode, TailMode::Plain) {
+            anyhow::bail!("--from-offset, --cursor and --since-time cannot be combined with -n, -f, --since, --delim or --until");
+        }
+    } else if json {
+        anyhow::bail!("--json requires --from-offset, --cursor or --since-time");
+    }
 
-    // 1. Check that the prompt is at the end of the output
-    prompt::check_prompt_ready(session, &prompt)?;
+    if stanza.is_some() && (follow || until.is_some() || lines.is_some() || plain || grep.is_some()
+        || matches!(mode, TailMode::Since(_)) || from_offset.is_some() || cursor.is_some() || since_time.is_some()) {
+        anyhow::bail!("--stanza can only be combined with --delim");
+    }
 
-    // 2. Record current file position before writing
-    let stdout_path = session::stdout_path(session)?;
-    let pos = {
-        use std::io::{Seek, SeekFrom};
-        let mut f = std::fs::File::open(&stdout_path)?;
-        f.seek(SeekFrom::End(0))?
-    };
+    if !budget.is_unlimited() && (follow || json || grep.is_some()) {
+        anyhow::bail!("--max-lines and --max-bytes cannot be combined with -f, --grep or --json");
+    }
+
+    if timestamps && !follow {
+        anyhow::bail!("--timestamps requires -f");
+    }
 
-    // 3. Write input (from args or stdin)
-    fifo::write_session(session, &input_args)?;
+    if (plain || grep.is_some()) && !(matches!(mode, TailMode::Plain) && (follow || (lines.is_some() && until.is_none()))) {
+        anyhow::bail!("--plain and --grep require -f or -n");
+    }
+
+    // Validate combinations
+    if follow && !matches!(mode, TailMode::Plain) {
+        anyhow::bail!("-f cannot be combined with --since or --delim");
+    }
+
+    if follow && matches!(until, Some(Completion::Idle(_))) {
+        anyhow::bail!("-f cannot be combined with --idle");
+    }
+
+    if timeout_secs.is_some() && until.is_none() {
+        anyhow::bail!("--timeout requires --until or --idle");
+    }
+
+    if until.is_some() && matches!(mode, TailMode::Delim(_)) {
+        anyhow::bail!("--until and --idle cannot be combined with --delim");
+    }
+
+    if matches!(mode, TailMode::Plain) && lines.is_none() && !follow && until.is_none()
+        && from_offset.is_none() && cursor.is_none() && since_time.is_none() && stanza.is_none() {
+        anyhow::bail!("usage: via <session> tail [-n N] (--since PROMPT | --delim PROMPT | --until PROMPT | --idle MS | -f | --from-offset N | --cursor NAME | --since-time DURATION | --stanza N)");
+    }
 
-    // 4. Stream output until the next prompt appears
-    tail::follow_until(session, &prompt, timeout, pos, &mut std::io::stdout())
+    Ok(TailOptions { mode, lines, follow, until, timeout_secs, from_offset, cursor, since_time, timestamps, plain, grep, stanza, budget, json })
 }
 
+/// Tail a session's output
+fn cmd_tail(session: &str, args: &[String]) -> Result<()> {
+    let opts = parse_tail_args(session, args)?;
+    let stdout_path = session::stdout_path(session)?;
+
+    // --from-offset / --cursor mode: everything new since the given offset
+    if opts.from_offset.is_some() || opts.cursor.is_some() {
+        return tail_incremental(session, opts.from_offset, opts.cursor.as_deref(), opts.json, &opts.budget);
+    }
+
+    // --since-time mode: everything written in the last DURATION, located via the index
+    if let Some(age) = opts.since_time {
+        let since = index::now_ms().saturating_sub(age.as_millis() as u64);
+        let offset = index::offset_since(&index::load(session)?, since);
+        return tail_incremental(session, Some(offset), None, opts.json, &opts.budget);
+    }
+
+    // --stanza mode: one exchange, addressed by its number in `via <session> stanzas`
+    if let Some(n) = opts.stanza {
+        let prompt = match opts.mode {
+            TailMode::Delim(prompt) => prompt,
+            _ => session::get_delim(session)?.ok_or_else(|| Error::NoDelim(session.to_string()))?,
+        };
+        let data = stanza::read_output(session)?;
+        let stanzas = stanza::numbered(&data, &prompt);
+        let found = stanza::nth(&stanzas, n)
+            .ok_or_else(|| anyhow::anyhow!("no stanza {} (session '{}' has {})", n, session, stanzas.len()))?;
+        let data = &data[found.start as usize..found.end as usize];
+        print_budgeted(session, data, &budget::raw_lines(data, found.start), &opts.budget)?;
+        return Ok(());
+    }
+
+    let filter = tail::LineFilter { timestamps: opts.timestamps, plain: opts.plain, grep: opts.grep };
+
+    // -f mode: follow new output, until a prompt appears if --until is given
+    if opts.follow {
+        let until = match opts.until {
+            Some(Completion::Prompt(ref prompt)) => Some(prompt.as_str()),
+            _ => None,
+        };
+        tail::follow_lines(session, opts.lines.unwrap_or(10), &filter, until, opts.timeout_secs, &mut std::io::stdout())?;
+        return Ok(());
+    }
+
+    // --until / --idle mode: stream output until the pattern matches or the session goes quiet
+    if let Some(ref completion) = opts.until {
+        let timeout = opts.timeout_secs.unwrap_or(tail::DEFAULT_TIMEOUT);
+
+        // If combined with --since, find the starting position from last prompt occurrence
+        let start_pos = if let TailMode::Since(ref since_prompt) = opts.mode {
+            tail::find_since_position(session, since_prompt)?
+        } else {
+            // Start from beginning so existing content is checked too
+            0
+        };
+
+        if opts.budget.is_unlimited() {
+            tail::follow(session, completion, timeout, start_pos, &mut std::io::stdout())?;
+        } else {
+            let mut text = Vec::new();
+            let mut lines = Vec::new();
+            tail::follow_ranges(session, completion, timeout, start_pos, &mut text, &mut lines)?;
+            print_budgeted(session, &text, &lines, &opts.budget)?;
+        }
+        return Ok(());
+    }
+
+    if !stdout_path.exists() {
+        anyhow::bail!("no stdout at {}", stdout_path.display());
+    }
+
+    match opts.mode {
+        TailMode::Plain => {
+            let n = opts.lines.unwrap(); // Already validated
+            let data = tail::tail_lines(session, n)?;
+            let start = std::fs::metadata(&stdout_path)?.len().saturating_sub(data.len() as u64);
+            if filter.plain || filter.grep.is_some() {
+                let mut filtered = Vec::new();
+                let mut lines = Vec::new();
+                for range in budget::raw_lines(&data, start) {
+                    let before = filtered.len();
+                    let line = &data[(range.start - start) as usize..(range.end - start) as usize];
+                    filter.write_line(&mut filtered, line, 0)?;
+                    if filtered.len() > before {
+                        lines.push(range);
+                    }
+                }
+                print_budgeted(session, &filtered, &lines, &opts.budget)?;
+            } else {
+                print_budgeted(session, &data, &budget::raw_lines(&data, start), &opts.budget)?;
+            }
+        }
+        TailMode::Since(prompt) if !opts.budget.is_unlimited() => {
+            let (start, data, _) = tail::read_from_offset(session, tail::find_since_position(session, &prompt)?)?;
+            print_budgeted(session, &data, &budget::raw_lines(&data, start), &opts.budget)?;
+        }
+        TailMode::Delim(prompt) if !opts.budget.is_unlimited() => {
+            // The last complete stanza: from the second-to-last prompt to the last
+            let data = stanza::read_output(session)?;
+            let (_, stanzas) = stanza::split(&data, &prompt);
+            let (start, end) = match stanzas.as_slice() {
+                [.., last, current] => (last.start, current.start),
+                [only] => (only.start, data.len() as u64),
+                [] => (data.len() as u64, data.len() as u64),
+            };
+            let data = &data[start as usize..end as usize];
+            print_budgeted(session, data, &budget::raw_lines(data, start), &opts.budget)?;
+        }
+        TailMode::Since(prompt) => {
+            for line in tail::tail_since(session, &prompt, opts.lines.unwrap_or(100))? {
+                println!("{}", line);
+            }
+        }
+        TailMode::Delim(prompt) => {
+            for line in tail::tail_delim_internal(session, &prompt, opts.lines.unwrap_or(100))? {
+                println!("{}", line);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Print a session's output, eliding its middle if it is over `budget`.
+/// `lines` holds the byte range in the stdout file behind each line.
+fn print_budgeted(session: &str, data: &[u8], lines: &[Range<u64>], budget: &Budget) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    if budget.is_unlimited() {
+        stdout.write_all(data)?;
+    } else {
+        let text = budget::elide(session, &String::from_utf8_lossy(data), lines, budget)?;
+        stdout.write_all(text.as_bytes())?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Print output from a byte offset (or a named cursor) up to the current end of
+/// the file, then report the new end offset so the next call can resume there.
+/// A cursor is advanced to the new end offset automatically.
+fn tail_incremental(session: &str, from_offset: Option<u64>, cursor: Option<&str>, json: bool, budget: &Budget) -> Result<()> {
+    let offset = match (from_offset, cursor) {
+        (Some(offset), _) => offset,
+        (None, Some(name)) => session::get_cursor(session, name)?.unwrap_or(0),
+        (None, None) => 0,
+    };
+
+    let (start, data, end) = tail::read_from_offset(session, offset)?;
+
+    if json {
+        let value = serde_json::json!({
+            "offset": start,
+            "end": end,
+            "output": String::from_utf8_lossy(&data),
+        });
+        let mut stdout = std::io::stdout();
+        writeln!(stdout, "{}", value)?;
+        stdout.flush()?;
+    } else {
+        print_budgeted(session, &data, &budget::raw_lines(&data, start), budget)?;
+        eprintln!("[via] offset: {}", end);
+    }
+
+    // Only once the output was delivered, so a failed read can be retried
+    if let Some(name) = cursor {
+        session::set_cursor(session, name, end)?;
+    }
+
+    Ok(())
+}
+
+/// Output: via <session> output OFFSET [LEN]
+/// Prints raw output by byte range, such as the part an output budget omitted.
+fn cmd_output(session: &str, args: &[String]) -> Result<()> {
+    let (offset, len) = match args {
+        [offset] => (offset, None),
+        [offset, len] => (offset, Some(len)),
+        _ => anyhow::bail!("usage: via {} output OFFSET [LEN]", session),
+    };
+    let offset = offset.parse().with_context(|| format!("invalid offset: {}", offset))?;
+    let len = len.map(|l| l.parse().with_context(|| format!("invalid length: {}", l))).transpose()?;
+
+    std::io::stdout().write_all(&budget::read_range(session, offset, len)?)?;
+    Ok(())
+}
+
+/// Attach: via <session> attach [--detach-key KEY]
+/// Takes over the session from this terminal until the detach key (Ctrl-] by default).
+fn cmd_attach(session: &str, args: &[String]) -> Result<()> {
+    let mut detach_key = attach::DEFAULT_DETACH_KEY;
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--detach-key" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--detach-key requires a key (e.g. '^]' or 'ctrl-a')");
+                }
+                detach_key = attach::parse_key(&args[i + 1])?;
+                i += 2;
+            }
+            other => anyhow::bail!("unknown attach option: {}", other),
+        }
+    }
+
+    Session::open(session)?;
+    eprintln!("[via] attached to '{}' (detach with {})", session, attach::describe_key(detach_key));
+    match attach::attach(session, detach_key)? {
+        attach::Ending::Detached => eprintln!("\n[via] detached from '{}'", session),
+        attach::Ending::SessionEnded => eprintln!("\n[via] session '{}' ended", session),
+    }
+    Ok(())
+}
+
+fn cmd_path(session: &str) -> Result<()> {
+    let path = session::session_path(session)?;
+    println!("{}", path.display());
+    Ok(())
+}
+
+/// Status: via <session> status
+/// Shows whether the session is running; for one that has ended, how and when
+/// it ended and the last lines it printed.
+fn cmd_status(session: &str) -> Result<()> {
+    let dir = session::session_path(session)?;
+    if !dir.exists() {
+        return Err(Error::NotFound(session.to_string()).into());
+    }
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).ok().map(|s| s.trim().to_string());
+
+    println!("session:  {}", session);
+    let tombstone = tombstone::read(session)?;
+    match tombstone {
+        Some(ref t) => println!("state:    {} at {}", t.describe(), index::format_time(t.ended_ms)),
+        None => match read("pid") {
+            Some(pid) => println!("state:    running (pid {})", pid),
+            None => println!("state:    starting"),
+        },
+    }
+    if let Some(command) = read("command") {
+        println!("command:  {}", command);
+    }
+    if let Some(cwd) = read("cwd") {
+        println!("cwd:      {}", cwd);
+    }
+    if let Some(group) = read("group") {
+        println!("group:    {}", group);
+    }
+    if dir.join("clear-env").exists() {
+        println!("env:      (cleared)");
+    }
+    if let Some(env) = read("env") {
+        for var in env.lines() {
+            println!("env:      {}", var);
+        }
+    }
+    if let Some(restarts) = read("restarts") {
+        let last = restarts.lines().last().and_then(|l| l.split_once(' ')).map(|(_, how)| how);
+        println!("restarts: {} (last: {})", restarts.lines().count(), last.unwrap_or("?"));
+    }
+
+    if let Some(t) = tombstone {
+        if !t.output.is_empty() {
+            println!("\nlast output:");
+            for line in t.output.lines() {
+                println!("  {}", line);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// GC: via gc [PATTERN]
+/// Removes the directories of ended sessions (all of them, or those matching
+/// PATTERN) and prints their names.
+fn cmd_gc(args: &[String]) -> Result<()> {
+    let pattern = match args {
+        [] => None,
+        [pattern] => Some(pattern.as_str()),
+        _ => anyhow::bail!("usage: via gc [PATTERN]"),
+    };
+
+    for info in session::list_sessions()? {
+        if info.ended.is_none() || pattern.is_some_and(|p| !session::matches_pattern(&info, p)) {
+            continue;
+        }
+        // The name may have been reclaimed since the listing; only remove it if
+        // it's still ended
+        match session::remove_ended(&info.name) {
+            Ok(true) => println!("{}", info.name),
+            Ok(false) => {}
+            Err(err) => eprintln!("[via] failed to remove {}: {}", info.name, err),
+        }
+    }
+    Ok(())
+}
+
+/// Log: via log [--since DURATION] [PATTERN]
+/// Merges the recent output of every session (or those matching PATTERN) into
+/// one chronological stream, each line prefixed with its time and session.
+fn cmd_log(args: &[String]) -> Result<()> {
+    let mut age = std::time::Duration::from_secs(600);
+    let mut pattern: Option<&str> = None;
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--since" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--since requires a duration (e.g. 5m)");
+                }
+                age = index::parse_duration(&args[i + 1])?;
+                i += 2;
+            }
+            other if pattern.is_none() && !other.starts_with('-') => {
+                pattern = Some(other);
+                i += 1;
+            }
+            other => anyhow::bail!("unknown log option: {}", other),
+        }
+    }
+
+    let sessions = match pattern {
+        Some(p) => session::matching_sessions(p)?,
+        None => session::list_sessions()?,
+    };
+    let since = index::now_ms().saturating_sub(age.as_millis() as u64);
+
+    let mut entries: Vec<(u64, &str, String)> = Vec::new();
+    for info in &sessions {
+        // A session may exit while we read it; skip it rather than failing the log
+        let lines = match index::lines_since(&info.name, since) {
+            Ok(lines) => lines,
+            Err(_) => continue,
+        };
+        for (time, line) in lines {
+            let text = via::prompt::process_terminal_output(line.strip_suffix(b"\n").unwrap_or(&line))?;
+            if time >= since && !text.is_empty() {
+                entries.push((time, &info.name, text));
+            }
+        }
+    }
+
+    // Stable sort keeps each session's lines in order within a checkpoint
+    entries.sort_by_key(|(time, _, _)| *time);
+
+    let width = sessions.iter().map(|info| info.name.len()).max().unwrap_or(0);
+    for (time, name, text) in entries {
+        println!("{} {:<width$}  {}", index::format_time(time), name, text, width = width);
+    }
+    Ok(())
+}
+
+/// Grep: via <session> grep [-i] [-C N] [--delim D] PATTERN
+/// or:   via grep [-i] [-C N] PATTERN [SESSIONS]  (all sessions, or a glob/@group)
+/// Exits 1 when nothing matches, like grep.
+fn cmd_grep(session: Option<&str>, args: &[String]) -> Result<i32> {
+    let usage = match session {
+        Some(name) => format!("usage: via {} grep [-i] [-C N] [--delim 'PROMPT>'] PATTERN", name),
+        None => "usage: via grep [-i] [-C N] PATTERN [SESSIONS]".to_string(),
+    };
+    let mut ignore_case = false;
+    let mut context = 0;
+    let mut delim: Option<String> = None;
+    let mut positional: Vec<&str> = Vec::new();
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "-i" | "--ignore-case" => {
+                ignore_case = true;
+                i += 1;
+            }
+            "-C" | "--context" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("{} requires a number of lines", args[i]);
+                }
+                context = args[i + 1].parse()
+                    .with_context(|| format!("invalid number: {}", args[i + 1]))?;
+                i += 2;
+            }
+            "--delim" if session.is_some() => {
+                let (val, consumed) = session::resolve_delim(session.unwrap(), args, i)?;
+                delim = Some(val);
+                i += consumed;
+            }
+            // Everything after "--" is positional, so patterns can start with '-'
+            "--" => {
+                positional.extend(args[i + 1..].iter().map(String::as_str));
+                break;
+            }
+            other if !other.starts_with('-') => {
+                positional.push(other);
+                i += 1;
+            }
+            other => anyhow::bail!("unknown grep option: {}", other),
+        }
+    }
+
+    let (pattern, filter) = match (session, positional.as_slice()) {
+        (Some(_), [pattern]) => (*pattern, None),
+        (None, [pattern]) => (*pattern, None),
+        (None, [pattern, filter]) => (*pattern, Some(*filter)),
+        _ => anyhow::bail!("{}", usage),
+    };
+    let regex = regex::RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .with_context(|| format!("invalid regex: {}", pattern))?;
+
+    let mut stdout = std::io::stdout().lock();
+    let mut matches = 0;
+    match session {
+        Some(name) => {
+            Session::find(name)?;
+            matches = search::grep(name, &regex, context, delim.as_deref(), false, &mut stdout)?;
+        }
+        None => {
+            let sessions = match filter {
+                Some(p) => session::matching_sessions(p)?,
+                None => session::list_sessions()?,
+            };
+            for info in &sessions {
+                // A session may exit while we read it; skip it rather than failing the search
+                matches += search::grep(&info.name, &regex, context, None, true, &mut stdout).unwrap_or(0);
+            }
+        }
+    }
+    Ok(if matches > 0 { 0 } else { 1 })
+}
+
+/// Export: via <session> export --format FMT [-o FILE] [--delim D]
+fn cmd_export(session: &str, args: &[String]) -> Result<()> {
+    let mut format: Option<export::Format> = None;
+    let mut output: Option<String> = None;
+    let mut delim: Option<String> = None;
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--format" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--format requires asciicast, markdown or html");
+                }
+                format = Some(args[i + 1].parse()?);
+                i += 2;
+            }
+            "-o" | "--output" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("{} requires a file name", args[i]);
+                }
+                output = Some(args[i + 1].clone());
+                i += 2;
+            }
+            "--delim" => {
+                let (val, consumed) = session::resolve_delim(session, args, i)?;
+                delim = Some(val);
+                i += consumed;
+            }
+            other => anyhow::bail!("unknown export option: {}", other),
+        }
+    }
+
+    let format = format
+        .ok_or_else(|| anyhow::anyhow!("usage: via {} export --format asciicast|markdown|html [-o FILE]", session))?;
+    Session::find(session)?;
+
+    match output {
+        Some(path) => {
+            let mut file = std::fs::File::create(&path)
+                .with_context(|| format!("failed to create {}", path))?;
+            export::export(session, format, delim.as_deref(), &mut file)?;
+            eprintln!("[via] wrote {}", path);
+        }
+        None => {
+            let stdout = std::io::stdout();
+            export::export(session, format, delim.as_deref(), &mut stdout.lock())?;
+        }
+    }
+    Ok(())
+}
+
+/// Stanzas: via <session> stanzas [--delim D]
+/// Lists each prompt-delimited exchange with its number, input and size.
+fn cmd_stanzas(session: &str, args: &[String]) -> Result<()> {
+    let mut delim: Option<String> = None;
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--delim" => {
+                let (val, consumed) = session::resolve_delim(session, args, i)?;
+                delim = Some(val);
+                i += consumed;
+            }
+            other => anyhow::bail!("unknown stanzas option: {}", other),
+        }
+    }
+
+    let prompt = match delim {
+        Some(d) => d,
+        None => session::get_delim(session)?.ok_or_else(|| Error::NoDelim(session.to_string()))?,
+    };
+    let data = stanza::read_output(session)?;
+    let stanzas = stanza::numbered(&data, &prompt);
+    if stanzas.is_empty() {
+        return Ok(());
+    }
+
+    let width = stanzas.len().to_string().len().max(6); // "Stanza" header
+    println!("{:>width$}  {:>5}  {:>8}  Input", "Stanza", "Lines", "Bytes", width = width);
+    for (n, s) in stanzas.iter().enumerate() {
+        let body = &data[s.body as usize..s.end as usize];
+        let lines = body.split_inclusive(|&b| b == b'\n').count();
+        println!("{:>width$}  {:>5}  {:>8}  {}", n + 1, lines, body.len(), s.input, width = width);
+    }
+    Ok(())
+}
+
+/// Record: via <session> record [-o FILE]
+fn cmd_record(session: &str, args: &[String]) -> Result<()> {
+    let output = match args {
+        [] => None,
+        [flag, path] if flag == "-o" || flag == "--output" => Some(path),
+        _ => anyhow::bail!("usage: via {} record [-o FILE]", session),
+    };
+
+    Session::find(session)?;
+    let recording = serde_json::to_string_pretty(&record::record(session)?)?;
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, recording + "\n")
+                .with_context(|| format!("failed to write {}", path))?;
+            eprintln!("[via] wrote {}", path);
+        }
+        None => println!("{}", recording),
+    }
+    Ok(())
+}
+
+/// Replay: via replay FILE [--speed N]
+fn cmd_replay(args: &[String]) -> Result<()> {
+    let mut file: Option<&str> = None;
+    let mut speed = 1.0;
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--speed" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--speed requires a number");
+                }
+                speed = args[i + 1].parse()
+                    .with_context(|| format!("invalid speed: {}", args[i + 1]))?;
+                i += 2;
+            }
+            other if file.is_none() => {
+                file = Some(other);
+                i += 1;
+            }
+            other => anyhow::bail!("unexpected argument: {}", other),
+        }
+    }
+
+    let file = file.ok_or_else(|| anyhow::anyhow!("usage: via replay FILE [--speed N]"))?;
+    let text = std::fs::read_to_string(file)
+        .with_context(|| format!("failed to read {}", file))?;
+    let recording = serde_json::from_str(&text)
+        .with_context(|| format!("invalid recording {}", file))?;
+
+    record::replay(&recording, speed, std::io::stdin().lock(), std::io::stdout().lock())
+}
+
+/// Shorthand: via <session> [--delim D | --idle MS] [--timeout N] [--max-lines N] [--max-bytes N] line...
+/// Uses stored delim (or idle time) if neither --delim nor --idle is provided.
+fn cmd_shorthand(session: &str, args: &[String]) -> Result<i32> {
+    let mut completion: Option<Completion> = None;
+    let mut timeout = tail::DEFAULT_TIMEOUT;
+    let mut budget = Budget::default();
+    let mut lockstep = false;
+    let mut input_args: Vec<String> = Vec::new();
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--lockstep" => {
+                lockstep = true;
+                i += 1;
+            }
+            "--delim" => {
+                let (val, consumed) = session::resolve_delim(session, args, i)?;
+                completion = Some(Completion::Prompt(val));
+                i += consumed;
+            }
+            "--idle" => {
+                completion = Some(Completion::Idle(idle_arg(args, i)?));
+                i += 2;
+            }
+            "--max-lines" => {
+                budget.max_lines = Some(count_arg(args, i)?);
+                i += 2;
+            }
+            "--max-bytes" => {
+                budget.max_bytes = Some(count_arg(args, i)?);
+                i += 2;
+            }
+            "--timeout" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--timeout requires a number");
+                }
+                timeout = args[i + 1].parse()
+                    .with_context(|| format!("invalid timeout: {}", args[i + 1]))?;
+                i += 2;
+            }
+            _ => {
+                input_args.push(args[i].clone());
+                i += 1;
+            }
+        }
+    }
+
+    let session = Session::open(session)?;
+    let completion = match completion {
+        Some(c) => c,
+        None => session::default_completion(session.name()).map_err(|_| anyhow::anyhow!(
+            "unknown subcommand or no stored delimiter for '{}' (try: via {} help)", session.name(), session.name()))?,
+    };
+
+    let input = read_input(&input_args)?;
+
+    if lockstep {
+        let patterns = lockstep::error_patterns(session.name())?;
+        return run_lockstep(&session, &input, &completion, timeout, &patterns, &budget);
+    }
+
+    // Show the output as it arrives, unless it has to fit a budget
+    let output = if budget.is_unlimited() {
+        session.send_and_stream(&input, &completion, timeout, &mut std::io::stdout())
+            .map_err(report_not_ready)?
+    } else {
+        let output = session.send_and_wait_until(&input, &completion, timeout)
+            .map_err(report_not_ready)?;
+        print_budgeted(session.name(), output.text.as_bytes(), &output.lines, &budget)?;
+        output
+    };
+
+    // With shell integration, exit with the shell command's status
+    Ok(output.status.unwrap_or(0))
+}
+
+/// Source: via <session> source FILE [--delim D | --idle MS] [--timeout N]
+///       [--error-pattern RE]... [--max-lines N] [--max-bytes N]
+/// Runs a script in lockstep, one command per prompt ("-" reads stdin).
+fn cmd_source(session: &str, args: &[String]) -> Result<i32> {
+    let mut completion: Option<Completion> = None;
+    let mut timeout = tail::DEFAULT_TIMEOUT;
+    let mut budget = Budget::default();
+    let mut errors: Vec<String> = Vec::new();
+    let mut file: Option<&str> = None;
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--delim" => {
+                let (val, consumed) = session::resolve_delim(session, args, i)?;
+                completion = Some(Completion::Prompt(val));
+                i += consumed;
+            }
+            "--idle" => {
+                completion = Some(Completion::Idle(idle_arg(args, i)?));
+                i += 2;
+            }
+            "--timeout" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--timeout requires a number");
+                }
+                timeout = args[i + 1].parse()
+                    .with_context(|| format!("invalid timeout: {}", args[i + 1]))?;
+                i += 2;
+            }
+            "--error-pattern" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--error-pattern requires a regex");
+                }
+                errors.push(args[i + 1].clone());
+                i += 2;
+            }
+            "--max-lines" => {
+                budget.max_lines = Some(count_arg(args, i)?);
+                i += 2;
+            }
+            "--max-bytes" => {
+                budget.max_bytes = Some(count_arg(args, i)?);
+                i += 2;
+            }
+            other if file.is_none() && (other == "-" || !other.starts_with('-')) => {
+                file = Some(other);
+                i += 1;
+            }
+            other => anyhow::bail!("unknown source option: {}", other),
+        }
+    }
+
+    let file = file.ok_or_else(|| anyhow::anyhow!("usage: via {} source FILE [--delim 'PROMPT>'] [--timeout N] [--error-pattern RE]", session))?;
+    let script = match file {
+        "-" => read_input(&[])?,
+        path => std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path))?,
+    };
+
+    let session = Session::open(session)?;
+    let completion = match completion {
+        Some(c) => c,
+        None => session::default_completion(session.name())?,
+    };
+    let patterns = if errors.is_empty() {
+        lockstep::error_patterns(session.name())?
+    } else {
+        lockstep::compile(&errors)?
+    };
+    run_lockstep(&session, &script, &completion, timeout, &patterns, &budget)
+}
+
+/// Send a script's commands one per prompt, printing each command's output
+/// under a header. Stops (exit status 1) at the first command that fails.
+fn run_lockstep(
+    session: &Session,
+    script: &str,
+    completion: &Completion,
+    timeout: f64,
+    patterns: &[regex::Regex],
+    budget: &Budget,
+) -> Result<i32> {
+    let commands = lockstep::commands(script);
+    let failure = lockstep::run(session, &commands, completion, timeout, patterns, |index, input, output| {
+        if index > 0 {
+            println!();
+        }
+        let first_line = input.lines().next().unwrap_or("");
+        let more = if input.lines().nth(1).is_some() { " ..." } else { "" };
+        println!("==> {}{} <==", first_line, more);
+        print_budgeted(session.name(), output.text.as_bytes(), &output.lines, budget)
+    }).map_err(|err| match err.downcast::<Error>() {
+        Ok(err) => report_not_ready(err).into(),
+        Err(err) => err,
+    })?;
+
+    match failure {
+        Some(failure) => {
+            eprintln!("[via] stopped at command {} of {} ({}): {}",
+                      failure.index + 1, commands.len(), commands[failure.index].lines().next().unwrap_or(""), failure.reason);
+            Ok(1)
+        }
+        None => Ok(0),
+    }
+}
+
+/// Broadcast: via --each PATTERN [--delim D | --idle MS] [--timeout N] line...
+/// Runs the shorthand's send-and-wait on every matching session in parallel,
+/// prints each session's output under a header, then a summary table. Without
+/// --delim or --idle, each session completes the way its shorthand would.
+fn cmd_each(args: &[String]) -> Result<i32> {
+    if args.is_empty() {
+        anyhow::bail!("usage: via --each 'PATTERN' [--delim D | --idle MS] [--timeout N] line...");
+    }
+    let pattern = &args[0];
+
+    let mut completion: Option<Completion> = None;
+    let mut timeout = tail::DEFAULT_TIMEOUT;
+    let mut input_args: Vec<String> = Vec::new();
+    let mut i = 1;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--delim" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--delim requires a value");
+                }
+                completion = Some(Completion::Prompt(args[i + 1].clone()));
+                i += 2;
+            }
+            "--idle" => {
+                completion = Some(Completion::Idle(idle_arg(args, i)?));
+                i += 2;
+            }
+            "--timeout" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--timeout requires a number");
+                }
+                timeout = args[i + 1].parse()
+                    .with_context(|| format!("invalid timeout: {}", args[i + 1]))?;
+                i += 2;
+            }
+            _ => {
+                input_args.push(args[i].clone());
+                i += 1;
+            }
+        }
+    }
+
+    let sessions = session::matching_sessions(pattern)?;
+    if sessions.is_empty() {
+        anyhow::bail!("no sessions match '{}'", pattern);
+    }
+
+    let input = read_input(&input_args)?;
+
+    // Send to every session in parallel
+    let handles: Vec<_> = sessions.into_iter()
+        .map(|info| {
+            let input = input.clone();
+            let completion = completion.clone();
+            std::thread::spawn(move || {
+                let started = std::time::Instant::now();
+                let result = Session::open(&info.name).and_then(|s| {
+                    // Each session's own delim, idle time, shell integration or sentinel
+                    let completion = match completion {
+                        Some(completion) => completion,
+                        None => session::default_completion(s.name())?,
+                    };
+                    s.send_and_wait_until(&input, &completion, timeout)
+                });
+                (info.name, result, started.elapsed())
+            })
+        })
+        .collect();
+    let results: Vec<_> = handles.into_iter()
+        .map(|h| h.join().expect("broadcast thread panicked"))
+        .collect();
+
+    // Output of each session under a header, like `tail` with several files
+    for (index, (name, result, _)) in results.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!("==> {} <==", name);
+        match result {
+            // Keep output that stops mid-line from running into the next header
+            Ok(output) if output.text.ends_with('\n') || output.text.is_empty() => print!("{}", output.text),
+            Ok(output) => println!("{}", output.text),
+            Err(err) => {
+                std::io::stdout().flush()?;
+                eprintln!("via: {}: {}", name, err);
+            }
+        }
+    }
+
+    // Summary table
+    let width = results.iter().map(|(name, _, _)| name.len()).max().unwrap_or(7).max(7);
+    println!();
+    println!("{:<width$}  {:<9}  Time", "Session", "Status", width = width);
+    for (name, result, elapsed) in &results {
+        let status = match result {
+            Ok(_) => "ok",
+            Err(Error::Timeout { .. }) => "timeout",
+            Err(Error::NotReady { .. }) => "not-ready",
+            Err(_) => "error",
+        };
+        println!("{:<width$}  {:<9}  {:.2}s", name, status, elapsed.as_secs_f64(), width = width);
+    }
+
+    let all_ok = results.iter().all(|(_, result, _)| result.is_ok());
+    Ok(if all_ok { 0 } else { 1 })
+}
+
+/// Pipe: via pipe <src> <dst> [--regex RE] [--delim D] [--timeout N]
+/// Takes the last stanza of <src>'s output (or a regex capture from it), sends
+/// it to <dst> and streams <dst>'s output until its prompt.
+fn cmd_pipe(args: &[String]) -> Result<()> {
+    let usage = "usage: via pipe <src> <dst> [--regex RE] [--delim 'PROMPT>'] [--timeout N]";
+    if args.len() < 2 {
+        anyhow::bail!("{}", usage);
+    }
+    let src = Session::open(&session::resolve_name(&args[0])?)?;
+    let dst = Session::open(&session::resolve_name(&args[1])?)?;
+
+    let mut regex: Option<regex::Regex> = None;
+    let mut delim: Option<String> = None;
+    let mut timeout = tail::DEFAULT_TIMEOUT;
+    let mut i = 2;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--regex" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--regex requires a pattern");
+                }
+                regex = Some(regex::Regex::new(&args[i + 1])
+                    .with_context(|| format!("invalid regex: {}", args[i + 1]))?);
+                i += 2;
+            }
+            "--delim" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--delim requires a value");
+                }
+                delim = Some(args[i + 1].clone());
+                i += 2;
+            }
+            "--timeout" => {
+                if i + 1 >= args.len() {
+                    anyhow::bail!("--timeout requires a number");
+                }
+                timeout = args[i + 1].parse()
+                    .with_context(|| format!("invalid timeout: {}", args[i + 1]))?;
+                i += 2;
+            }
+            _ => anyhow::bail!("{}", usage),
+        }
+    }
+
+    // The source's last stanza, without the prompt line echoing its input
+    let src_delim = src.delim()?.ok_or_else(|| Error::NoDelim(src.name().to_string()))?;
+    let output = tail::last_stanza_output(src.name(), &src_delim)?;
+
+    // Optionally narrow it down to a regex match (first capture group if any)
+    let value = match regex {
+        Some(re) => {
+            let captures = re.captures(&output)
+                .ok_or_else(|| anyhow::anyhow!("no match for /{}/ in the output of '{}'", re, src.name()))?;
+            captures.get(1).or_else(|| captures.get(0)).unwrap().as_str().to_string()
+        }
+        None => output,
+    };
+    if value.is_empty() {
+        anyhow::bail!("nothing to pipe: the last stanza of '{}' has no output", src.name());
+    }
+
+    let output = dst.send_and_wait(&value, delim.as_deref(), timeout)
+        .map_err(report_not_ready)?;
+    print!("{}", output.text);
+    Ok(())
+}
+
+/// Input comes from the args joined as a single line, or all of stdin
+fn read_input(input_args: &[String]) -> Result<String> {
+    if !input_args.is_empty() {
+        return Ok(input_args.join(" "));
+    }
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)
+        .with_context(|| "failed to read from stdin")?;
+    Ok(input)
+}
+
+/// Show what was found at the end of the output when a session isn't at its
+/// prompt, with control characters made visible
+fn report_not_ready(err: Error) -> Error {
+    if let Error::NotReady { ref prompt, ref found } = err {
+        eprintln!("Session not ready. Expected prompt '{}' at end, but found:", prompt);
+        eprintln!();
+
+        let mut stderr = std::io::stderr();
+        for &byte in found.as_bytes() {
+            if byte < 32 || byte == 127 {
+                // Show control characters in caret notation
+                let _ = match byte {
+                    0..=26 => write!(stderr, "^{}", (byte + 64) as char),
+                    27 => write!(stderr, "^["),
+                    28 => write!(stderr, "^\\"),
+                    29 => write!(stderr, "^]"),
+                    30 => write!(stderr, "^^"),
+                    31 => write!(stderr, "^_"),
+                    127 => write!(stderr, "^?"),
+                    _ => write!(stderr, "{}", byte as char),
+                };
+            } else {
+                let _ = write!(stderr, "{}", byte as char);
+            }
+        }
+        eprintln!();
+        eprintln!();
+    }
+    err
+}
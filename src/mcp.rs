@@ -0,0 +1,367 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+
+use crate::error::Error;
+use crate::sentinel;
+use crate::session::Session;
+use crate::supervisor::{self, LaunchOptions};
+use crate::tail::{self, Completion};
+
+// Model Context Protocol server over stdio. Messages are JSON-RPC 2.0 objects,
+// one per line. Sessions are exposed as tools; each tool result carries a text
+// rendering for display and, from protocol version 2025-06-18 on,
+// `structuredContent` for programs.
+
+/// Protocol versions we speak, newest first. A client asking for one of these
+/// gets it; any other request is answered with the newest.
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// First protocol version with `structuredContent` in tool results
+const STRUCTURED_CONTENT_VERSION: &str = "2025-06-18";
+
+/// Serve MCP requests from `input` until it closes, writing responses to `output`
+pub fn serve(input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut version = PROTOCOL_VERSIONS[0];
+    for line in input.lines() {
+        let line = line.with_context(|| "failed to read request")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => handle_message(&message, &mut version),
+            Err(err) => Some(rpc_error(Value::Null, -32700, &format!("parse error: {}", err))),
+        };
+
+        if let Some(response) = response {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Handle one JSON-RPC message. Notifications (no `id`) get no response.
+/// `initialize` settles the protocol `version` for the rest of the session.
+fn handle_message(message: &Value, version: &mut &'static str) -> Option<Value> {
+    let id = message.get("id").cloned();
+    let method = message["method"].as_str().unwrap_or("");
+    let params = &message["params"];
+
+    let result = match method {
+        "initialize" => {
+            let requested = params["protocolVersion"].as_str();
+            *version = PROTOCOL_VERSIONS.iter()
+                .find(|v| Some(**v) == requested)
+                .unwrap_or(&PROTOCOL_VERSIONS[0]);
+            Ok(json!({
+                "protocolVersion": *version,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "via", "version": env!("CARGO_PKG_VERSION") },
+            }))
+        }
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tool_definitions() })),
+        "tools/call" => Ok(call_tool(params, *version >= STRUCTURED_CONTENT_VERSION)),
+        _ if method.starts_with("notifications/") => return None,
+        _ => Err((-32601, format!("method not found: {}", method))),
+    };
+
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => rpc_error(id, code, &message),
+    })
+}
+
+fn rpc_error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn tool_definitions() -> Value {
+    json!([
+        {
+            "name": "list_sessions",
+            "description": "List via sessions with their state (running, or how they ended), command, working directory and current prompt line.",
+            "inputSchema": { "type": "object", "properties": {} },
+        },
+        {
+            "name": "start_session",
+            "description": "Start a command (REPL, shell, ...) as a background session.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "command": { "type": "array", "items": { "type": "string" }, "description": "Command and arguments" },
+                    "name": { "type": "string", "description": "Session name (generated from the command if omitted)" },
+                    "delim": { "type": "string", "description": "Prompt text that marks the session as ready for input" },
+                    "group": { "type": "string", "description": "Group to add the session to" },
+                    "idle": { "type": "integer", "description": "Milliseconds of silence that complete a response, for programs without a prompt" },
+                    "shell": { "type": "boolean", "description": "Install shell integration (bash, zsh, fish) so eval reports exit statuses" },
+                    "sentinel": { "type": ["boolean", "string"], "description": "End each response at a unique marker printed by a command sent after the input: true for the command's preset, or a template containing {marker}" },
+                    "cwd": { "type": "string", "description": "Directory to run the command in" },
+                    "env": { "type": "object", "additionalProperties": { "type": "string" }, "description": "Variables to set in the command's environment" },
+                },
+                "required": ["command"],
+            },
+        },
+        {
+            "name": "eval",
+            "description": "Send input to a session and return its output up to the next prompt.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session": { "type": "string" },
+                    "input": { "type": "string" },
+                    "delim": { "type": "string", "description": "Prompt to wait for (default: stored delim)" },
+                    "idle": { "type": "integer", "description": "Instead of a prompt, wait for this many milliseconds of silence" },
+                    "timeout": { "type": "number", "description": "Seconds to wait (default 30)" },
+                },
+                "required": ["session", "input"],
+            },
+        },
+        {
+            "name": "tail",
+            "description": "Read a session's output, even after it has ended: everything since a byte offset, the last stanza, or the last N lines.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session": { "type": "string" },
+                    "offset": { "type": "integer", "description": "Return output written since this byte offset" },
+                    "stanza": { "type": "boolean", "description": "Return the last prompt-delimited stanza" },
+                    "lines": { "type": "integer", "description": "Number of trailing lines (default 50)" },
+                },
+                "required": ["session"],
+            },
+        },
+        {
+            "name": "wait",
+            "description": "Wait until a prompt (default: stored delim) appears in a session's output.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session": { "type": "string" },
+                    "until": { "type": "string" },
+                    "idle": { "type": "integer", "description": "Instead of a prompt, wait for this many milliseconds of silence" },
+                    "timeout": { "type": "number", "description": "Seconds to wait (default 30)" },
+                },
+                "required": ["session"],
+            },
+        },
+        {
+            "name": "stop_session",
+            "description": "Terminate a session's process. The session stays listed as ended, its output still readable with tail, until `via gc` removes it (or right away if it was started with --rm).",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "session": { "type": "string" } },
+                "required": ["session"],
+            },
+        },
+    ])
+}
+
+/// Run a tool and wrap its outcome as an MCP tool result, with
+/// `structuredContent` if the client understands it. Tool failures are
+/// reported in the result (`isError`) rather than as JSON-RPC errors.
+fn call_tool(params: &Value, structured_content: bool) -> Value {
+    let name = params["name"].as_str().unwrap_or("");
+    let args = &params["arguments"];
+
+    let outcome = match name {
+        "list_sessions" => tool_list_sessions(),
+        "start_session" => tool_start_session(args),
+        "eval" => tool_eval(args),
+        "tail" => tool_tail(args),
+        "wait" => tool_wait(args),
+        "stop_session" => tool_stop_session(args),
+        _ => Err(Error::Other(format!("unknown tool: {}", name))),
+    };
+
+    let (text, structured, is_error) = match outcome {
+        Ok((text, structured)) => (text, structured, false),
+        Err(err) => (err.to_string(), error_details(&err), true),
+    };
+    let mut result = json!({
+        "content": [{ "type": "text", "text": text }],
+        "isError": is_error,
+    });
+    if structured_content {
+        result["structuredContent"] = structured;
+    }
+    result
+}
+
+fn error_details(err: &Error) -> Value {
+    let mut details = json!({ "error": err.kind(), "message": err.to_string() });
+    match err {
+        Error::NotReady { prompt, found } => {
+            details["prompt"] = json!(prompt);
+            details["found"] = json!(found);
+        }
+        Error::Timeout { pattern, secs } => {
+            details["pattern"] = json!(pattern);
+            details["secs"] = json!(secs);
+        }
+        _ => {}
+    }
+    details
+}
+
+type ToolResult = crate::Result<(String, Value)>;
+
+fn str_arg<'a>(args: &'a Value, name: &str) -> crate::Result<&'a str> {
+    args[name].as_str()
+        .ok_or_else(|| Error::Other(format!("missing argument '{}'", name)))
+}
+
+fn timeout_arg(args: &Value) -> crate::Result<f64> {
+    match &args["timeout"] {
+        Value::Null => Ok(tail::DEFAULT_TIMEOUT),
+        value => value.as_f64()
+            .ok_or_else(|| Error::Other(format!("invalid timeout: {} (use a positive number of seconds)", value)))
+            .and_then(|secs| Ok(tail::check_timeout(secs)?)),
+    }
+}
+
+fn tool_list_sessions() -> ToolResult {
+    let sessions = Session::list()?;
+    let state = |info: &crate::SessionInfo| match info.ended {
+        Some(ref tombstone) => tombstone.state(),
+        None => "running".to_string(),
+    };
+    let text = sessions.iter()
+        .map(|info| format!("{}\t{}\t{}\t{}",
+                            info.name,
+                            state(info),
+                            info.prompt.as_deref().unwrap_or(""),
+                            info.command.as_deref().unwrap_or("")))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let structured: Vec<Value> = sessions.iter()
+        .map(|info| json!({
+            "name": info.name,
+            "command": info.command,
+            "cwd": info.cwd,
+            "group": info.group,
+            "prompt": info.prompt,
+            "state": state(info),
+            "ended": info.ended.as_ref().map(|t| json!({
+                "exit_code": t.exit_code,
+                "signal": t.signal,
+                "output": t.output,
+            })),
+        }))
+        .collect();
+    Ok((text, json!({ "sessions": structured })))
+}
+
+fn tool_start_session(args: &Value) -> ToolResult {
+    let command: Vec<String> = args["command"].as_array()
+        .map(|items| items.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+        .unwrap_or_default();
+    if command.is_empty() {
+        return Err(Error::Other("missing argument 'command'".to_string()));
+    }
+
+    let sentinel = match &args["sentinel"] {
+        Value::String(template) => Some(template.clone()),
+        Value::Bool(true) => Some(sentinel::preset(&command)
+            .ok_or_else(|| Error::Other(format!("no sentinel preset for '{}'", command[0])))?
+            .to_string()),
+        _ => None,
+    };
+    if let Some(ref template) = sentinel {
+        sentinel::validate(template)?;
+    }
+
+    if let Some(cwd) = args["cwd"].as_str() {
+        if !std::path::Path::new(cwd).is_dir() {
+            return Err(Error::Other(format!("cwd {} is not a directory", cwd)));
+        }
+    }
+
+    let mut env = Vec::new();
+    if let Some(vars) = args["env"].as_object() {
+        for (key, value) in vars {
+            let value = value.as_str()
+                .ok_or_else(|| Error::Other(format!("env value for {} must be a string", key)))?;
+            env.push(supervisor::parse_env(&format!("{}={}", key, value))?);
+        }
+    }
+
+    let options = LaunchOptions {
+        delim: args["delim"].as_str().map(String::from),
+        group: args["group"].as_str().map(String::from),
+        idle: args["idle"].as_u64(),
+        shell: args["shell"].as_bool() == Some(true),
+        sentinel,
+        errors: Vec::new(),
+        cwd: args["cwd"].as_str().map(std::path::PathBuf::from),
+        env,
+        ..LaunchOptions::default()
+    };
+
+    let session = Session::spawn(args["name"].as_str(), &command, &options)?;
+    let name = session.name();
+    Ok((format!("started session '{}'", name), json!({
+        "session": name,
+        "dir": session.dir().display().to_string(),
+    })))
+}
+
+fn tool_eval(args: &Value) -> ToolResult {
+    let session = Session::open(str_arg(args, "session")?)?;
+    let input = str_arg(args, "input")?;
+    let output = match args["idle"].as_u64() {
+        Some(ms) => session.send_and_wait_until(input, &Completion::Idle(ms), timeout_arg(args)?)?,
+        None => session.send_and_wait(input, args["delim"].as_str(), timeout_arg(args)?)?,
+    };
+    Ok((output.text.clone(), json!({
+        "output": output.text,
+        "offset": output.offset,
+        "end": output.end,
+        "status": output.status,
+    })))
+}
+
+fn tool_tail(args: &Value) -> ToolResult {
+    let name = str_arg(args, "session")?;
+    // An ended session's transcript is still there to read
+    let session = Session::find(name)?;
+
+    if let Some(offset) = args["offset"].as_u64() {
+        let output = session.read_since(offset)?;
+        return Ok((output.text.clone(), json!({
+            "output": output.text,
+            "offset": output.offset,
+            "end": output.end,
+        })));
+    }
+
+    let text = if args["stanza"].as_bool() == Some(true) {
+        let delim = session.delim()?.ok_or_else(|| Error::NoDelim(name.to_string()))?;
+        let mut lines = tail::tail_delim_internal(name, &delim, 100)?.join("\n");
+        lines.push('\n');
+        lines
+    } else {
+        let lines = args["lines"].as_u64().unwrap_or(50) as usize;
+        String::from_utf8_lossy(&tail::tail_lines(name, lines)?).into_owned()
+    };
+    Ok((text.clone(), json!({ "output": text })))
+}
+
+fn tool_wait(args: &Value) -> ToolResult {
+    let session = Session::open(str_arg(args, "session")?)?;
+    let output = match args["idle"].as_u64() {
+        Some(ms) => session.wait_until(&Completion::Idle(ms), timeout_arg(args)?)?,
+        None => session.wait_for(args["until"].as_str(), timeout_arg(args)?)?,
+    };
+    Ok(("ready".to_string(), json!({ "ready": true, "end": output.end })))
+}
+
+fn tool_stop_session(args: &Value) -> ToolResult {
+    let name = str_arg(args, "session")?;
+    Session::open(name)?.stop(5.0)?;
+    Ok((format!("stopped session '{}'", name), json!({ "session": name, "stopped": true })))
+}
@@ -1,11 +1,28 @@
 use anyhow::{Context, Result};
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Read, Seek, SeekFrom};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::error::Error;
+use crate::fifo;
 use crate::session;
+use crate::tail::{self, Completion};
 
 /// Check if a prompt is ready (appears at the end of session output)
 pub fn check_prompt_ready(session: &str, prompt: &str) -> Result<()> {
+    let last_content = last_line(session)?;
+
+    // Check if it ends with the prompt
+    if !last_content.ends_with(prompt) {
+        return Err(Error::NotReady { prompt: prompt.to_string(), found: last_content }.into());
+    }
+
+    Ok(())
+}
+
+/// The last line of a session's output, cleaned up for display
+pub fn last_line(session: &str) -> Result<String> {
     let stdout_path = session::stdout_path(session)?;
 
     if !stdout_path.exists() {
@@ -27,39 +44,70 @@ pub fn check_prompt_ready(session: &str, prompt: &str) -> Result<()> {
         .with_context(|| "failed to read from stdout")?;
 
     // Process the content
-    let last_content = process_terminal_output(&buffer)?;
+    process_terminal_output(&buffer)
+}
 
-    // Check if it ends with the prompt
-    if !last_content.ends_with(prompt) {
-        eprintln!("Session not ready. Expected prompt '{}' at end, but found:", prompt);
-        eprintln!();
-
-        // Show the content with visible control characters
-        let mut stderr = std::io::stderr();
-        for &byte in last_content.as_bytes() {
-            if byte < 32 || byte == 127 {
-                // Show control characters in caret notation
-                match byte {
-                    0..=26 => write!(stderr, "^{}", (byte + 64) as char)?,
-                    27 => write!(stderr, "^[")?,
-                    28 => write!(stderr, "^\\")?,
-                    29 => write!(stderr, "^]")?,
-                    30 => write!(stderr, "^^")?,
-                    31 => write!(stderr, "^_")?,
-                    127 => write!(stderr, "^?")?,
-                    _ => write!(stderr, "{}", byte as char)?,
-                }
-            } else {
-                write!(stderr, "{}", byte as char)?;
-            }
+/// The shortest prompt `detect_delim` accepts (e.g. node's `> `)
+const MIN_DELIM_CHARS: usize = 2;
+
+/// Work out a session's prompt: wait for startup output to settle, note the
+/// last line, submit an empty line and note the last line again. The text the
+/// two lines end with is the part of the prompt that reappears after every
+/// submission (so numbered prompts like `In [1]:` still work).
+pub fn detect_delim(session: &str, idle_ms: u64, timeout_secs: f64) -> Result<String> {
+    let deadline = tail::deadline(timeout_secs)?;
+
+    let before = settled_last_line(session, 0, idle_ms, deadline, timeout_secs)?;
+    let offset = std::fs::metadata(session::stdout_path(session)?)?.len();
+    fifo::write_input(session, "")?;
+    let after = settled_last_line(session, offset, idle_ms, deadline, timeout_secs)?;
+
+    let suffix = common_suffix(&before, &after).trim_start().to_string();
+    if suffix.is_empty() {
+        anyhow::bail!("no common prompt found (last lines were {:?} and {:?})", before, after);
+    }
+    // A single character would turn up in ordinary output and end responses early
+    if suffix.chars().count() < MIN_DELIM_CHARS {
+        anyhow::bail!("detected prompt {:?} is too short to tell apart from output (use --delim)", suffix);
+    }
+    Ok(suffix)
+}
+
+/// Wait until the session has written past `offset` and then been silent for
+/// `idle_ms`, and return its non-empty last line
+fn settled_last_line(session: &str, offset: u64, idle_ms: u64, deadline: Instant, timeout_secs: f64) -> Result<String> {
+    let stdout_path = session::stdout_path(session)?;
+    let timeout = || Error::Timeout { pattern: "a prompt".to_string(), secs: timeout_secs };
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now()).as_secs_f64();
+        if remaining <= 0.0 {
+            return Err(timeout().into());
         }
-        writeln!(stderr)?;
-        eprintln!();
 
-        anyhow::bail!("Session may contain unprocessed input");
+        let settled = tail::follow(session, &Completion::Idle(idle_ms), remaining, offset, &mut std::io::sink());
+        if settled.is_err() && Instant::now() >= deadline {
+            return Err(timeout().into());
+        }
+        settled?;
+        let grown = std::fs::metadata(&stdout_path).map(|m| m.len() > offset).unwrap_or(false);
+        if grown {
+            let line = last_line(session)?;
+            if !line.is_empty() {
+                return Ok(line);
+            }
+        }
+        thread::sleep(Duration::from_millis(idle_ms.min(100)));
     }
+}
 
-    Ok(())
+/// The longest common suffix of two strings, on character boundaries
+fn common_suffix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len: usize = a.chars().rev()
+        .zip(b.chars().rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum();
+    &a[a.len() - len..]
 }
 
 /// Strip all ANSI escape sequences from raw bytes using the vte parser.
@@ -76,7 +124,7 @@ pub fn process_terminal_output(data: &[u8]) -> Result<String> {
     let mut content = strip_ansi(data);
 
     // 2. Process carriage returns: split on \r and take the last segment
-    if let Some(last_segment) = content.split('\r').last() {
+    if let Some(last_segment) = content.split('\r').next_back() {
         content = last_segment.to_string();
     }
 
@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::thread;
+use std::time::Duration;
+
+use crate::fifo;
+use crate::index;
+use crate::prompt;
+use crate::sentinel;
+use crate::session;
+use crate::stanza;
+
+// Recordings are JSON fixtures:
+//
+//   {
+//     "command": "ghci",
+//     "prompt": "ghci> ",
+//     "banner": "GHCi, version 9.4.8 ...\nghci> ",
+//     "exchanges": [
+//       { "input": "1 + 1", "output": "2\nghci> ", "delay_ms": 40 }
+//     ]
+//   }
+//
+// `banner` is everything printed before the first input, `output` is what an
+// input produced up to the next input (without the terminal's echo of it), and
+// `delay_ms` is how long the output took to complete. Line endings are stored
+// as "\n"; the pty a replay runs under turns them back into "\r\n". In a
+// sentinel session the sentinel commands and their markers are left out.
+
+/// Build a recording of a session's exchanges so far from its input log,
+/// output file and timing index
+pub fn record(session: &str) -> Result<Value> {
+    let dir = session::session_path(session)?;
+    let data = stanza::read_output(session)?;
+    let checkpoints = index::load(session)?;
+    let inputs = load_inputs(session)?;
+    let command = std::fs::read_to_string(dir.join("command")).unwrap_or_default();
+    let template = sentinel::get_template(session)?;
+    let clean = |text: String| match template {
+        Some(ref template) => sentinel::strip_lines(&text, template),
+        None => text,
+    };
+
+    let first = inputs.first().map(|i| i.offset).unwrap_or(data.len() as u64);
+    let banner = clean(normalize(&data[..clamp(first, &data)]));
+    let prompt = banner.rsplit('\n').next().unwrap_or("").to_string();
+
+    let mut exchanges = Vec::new();
+    for (i, input) in inputs.iter().enumerate() {
+        let start = clamp(input.offset, &data);
+        let end = inputs.get(i + 1).map(|next| clamp(next.offset, &data)).unwrap_or(data.len());
+        if end < start {
+            continue;
+        }
+
+        let output = clean(strip_echo(&normalize(&data[start..end]), &input.input));
+        let delay_ms = if end > start {
+            index::time_of(&checkpoints, end as u64 - 1).saturating_sub(input.time)
+        } else {
+            0
+        };
+        exchanges.push(json!({ "input": input.input, "output": output, "delay_ms": delay_ms }));
+    }
+
+    Ok(json!({
+        "command": command.trim(),
+        "prompt": prompt,
+        "banner": banner,
+        "exchanges": exchanges,
+    }))
+}
+
+/// One entry of a session's input log
+struct LoggedInput {
+    time: u64,
+    offset: u64,
+    input: String,
+}
+
+fn load_inputs(session: &str) -> Result<Vec<LoggedInput>> {
+    let path = fifo::inputs_path(session)?;
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    Ok(text.lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|entry| Some(LoggedInput {
+            time: entry["time"].as_u64()?,
+            offset: entry["offset"].as_u64()?,
+            input: entry["input"].as_str()?.to_string(),
+        }))
+        .collect())
+}
+
+fn clamp(offset: u64, data: &[u8]) -> usize {
+    std::cmp::min(offset as usize, data.len())
+}
+
+/// Turn captured pty output into the text a program would print: "\r\n" line
+/// endings become "\n"
+fn normalize(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).replace("\r\n", "\n")
+}
+
+/// Drop the terminal's echo of `input` from the start of its output. Lines
+/// after the first may be echoed after a continuation prompt such as `... `.
+pub(crate) fn strip_echo(output: &str, input: &str) -> String {
+    let mut rest = output;
+    // An empty input still echoes an empty line
+    let lines: Vec<&str> = if input.is_empty() { vec![""] } else { input.lines().collect() };
+    for (i, line) in lines.iter().enumerate() {
+        let Some((first, tail)) = rest.split_once('\n') else { break };
+        let echoed = prompt::strip_ansi(first.as_bytes());
+        let echoed = echoed.trim_end();
+        let line = line.trim_end();
+        if echoed != line && (i == 0 || line.is_empty() || !echoed.ends_with(line)) {
+            break;
+        }
+        rest = tail;
+    }
+    rest.to_string()
+}
+
+/// Act as the recorded program: print the banner, then answer each input with
+/// the output recorded for it. Inputs are matched in recorded order, falling
+/// back to any exchange with the same input; lines that begin a multi-line
+/// input are held until the rest of it arrives. `speed` scales the recorded
+/// delays (0 answers immediately).
+pub fn replay(recording: &Value, speed: f64, input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let exchanges = recording["exchanges"].as_array()
+        .with_context(|| "recording has no exchanges")?;
+    let prompt = recording["prompt"].as_str().unwrap_or("");
+
+    output.write_all(recording["banner"].as_str().unwrap_or("").as_bytes())?;
+    output.flush()?;
+
+    let mut next = 0;
+    let mut pending = String::new();
+    for line in input.lines() {
+        let line = line.with_context(|| "failed to read input")?;
+        let line = match std::mem::take(&mut pending) {
+            held if held.is_empty() => line,
+            held => format!("{}\n{}", held, line),
+        };
+
+        let matches = |e: &Value| e["input"].as_str() == Some(line.as_str());
+
+        // Wait for the rest of a recorded multi-line input, unless the line is
+        // a whole input of its own that isn't next in order
+        let continues = |e: &Value| e["input"].as_str()
+            .is_some_and(|input| input.strip_prefix(line.as_str()).is_some_and(|rest| rest.starts_with('\n')));
+        if exchanges.get(next).is_some_and(continues)
+            || (!exchanges.iter().any(matches) && exchanges.iter().any(continues)) {
+            pending = line;
+            continue;
+        }
+
+        let found = match exchanges.get(next) {
+            Some(e) if matches(e) => Some(next),
+            _ => exchanges.iter().position(matches),
+        };
+
+        match found {
+            Some(i) => {
+                next = i + 1;
+                let exchange = &exchanges[i];
+                if speed > 0.0 {
+                    let delay = exchange["delay_ms"].as_u64().unwrap_or(0) as f64 / speed;
+                    thread::sleep(Duration::from_millis(delay as u64));
+                }
+                output.write_all(exchange["output"].as_str().unwrap_or("").as_bytes())?;
+            }
+            None => write!(output, "via replay: no recorded output for '{}'\n{}", line, prompt)?,
+        }
+        output.flush()?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use regex::Regex;
+use std::io::Write;
+
+use crate::prompt;
+use crate::session;
+use crate::stanza::{self, Stanza};
+
+// `via grep` output: matching lines (`N:text`) and context lines (`N-text`),
+// numbered from the start of the session's output. A header names the stanza
+// (and the input that produced it) whenever the hits move to a new one, and
+// `--` separates non-adjacent hits within a stanza:
+//
+//   == stanza 3: :load Foo.hs ==
+//   412-[1 of 2] Compiling Foo
+//   413:Foo.hs:12:5: error:
+//
+// Searching several sessions puts the session name in the header.
+
+/// Search a session's whole output (escape sequences removed) for `regex`,
+/// with `context` lines around each hit. `delim` overrides the stored prompt
+/// used to find stanzas; without either, hits are not annotated. `label` puts
+/// the session name in the headers. Returns the number of matching lines.
+pub fn grep(
+    session: &str,
+    regex: &Regex,
+    context: usize,
+    delim: Option<&str>,
+    label: bool,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    let data = stanza::read_output(session)?;
+    let delim = match delim {
+        Some(d) => Some(d.to_string()),
+        None => session::get_delim(session)?,
+    };
+    let stanzas = match delim {
+        Some(ref d) => stanza::split(&data, d).1,
+        None => Vec::new(),
+    };
+
+    let mut lines = Vec::new();
+    let mut offset = 0u64;
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        let text = line.strip_suffix(b"\n").unwrap_or(line);
+        lines.push((offset, prompt::process_terminal_output(text)?));
+        offset += line.len() as u64;
+    }
+
+    let hits: Vec<usize> = (0..lines.len()).filter(|&i| regex.is_match(&lines[i].1)).collect();
+    let mut shown: Vec<usize> = hits.iter()
+        .flat_map(|&i| i.saturating_sub(context)..=(i + context).min(lines.len() - 1))
+        .collect();
+    shown.sort_unstable();
+    shown.dedup();
+
+    let mut previous: Option<(usize, Option<usize>)> = None;
+    for i in shown {
+        let (offset, ref text) = lines[i];
+        let stanza = stanzas.iter().rposition(|s| s.start <= offset);
+
+        match previous {
+            Some((_, last_stanza)) if last_stanza != stanza => {
+                write_header(writer, session, label, &stanzas, stanza)?;
+            }
+            Some((last, _)) if last + 1 != i => writeln!(writer, "--")?,
+            Some(_) => {}
+            None if label || !stanzas.is_empty() => {
+                write_header(writer, session, label, &stanzas, stanza)?;
+            }
+            None => {}
+        }
+
+        let mark = if hits.binary_search(&i).is_ok() { ':' } else { '-' };
+        writeln!(writer, "{}{}{}", i + 1, mark, text)?;
+        previous = Some((i, stanza));
+    }
+
+    Ok(hits.len())
+}
+
+/// Name the stanza (numbered from 1) that the following lines belong to
+fn write_header(
+    writer: &mut dyn Write,
+    session: &str,
+    label: bool,
+    stanzas: &[Stanza],
+    stanza: Option<usize>,
+) -> Result<()> {
+    let mut parts = Vec::new();
+    if label {
+        parts.push(session.to_string());
+    }
+    match stanza {
+        Some(n) if stanzas[n].input.is_empty() => parts.push(format!("stanza {}", n + 1)),
+        Some(n) => parts.push(format!("stanza {}: {}", n + 1, stanzas[n].input)),
+        None if !stanzas.is_empty() => parts.push("banner".to_string()),
+        None => {}
+    }
+    writeln!(writer, "== {} ==", parts.join(": "))?;
+    Ok(())
+}
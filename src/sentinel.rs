@@ -0,0 +1,96 @@
+use anyhow::Result;
+use regex::Regex;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::prompt;
+use crate::session;
+use crate::tail::Completion;
+
+// Sentinel completion: after each input via sends a command that prints a
+// unique marker (`VIA-<nonce>`), and the response ends exactly when a line
+// consisting of that marker appears. Templates spell the command for a given
+// language, with `{marker}` standing for the marker.
+
+/// Placeholder for the marker in sentinel templates
+pub const PLACEHOLDER: &str = "{marker}";
+
+/// Sentinel templates for common programs, by program name
+const PRESETS: &[(&[&str], &str)] = &[
+    (&["python", "python3", "ipython", "ipython3", "lua", "luajit"], "print(\"{marker}\")"),
+    (&["bash", "sh", "zsh", "dash", "ksh", "fish"], "echo {marker}"),
+    (&["ghci", "cabal", "stack"], "putStrLn \"{marker}\""),
+    (&["node", "deno"], "console.log(\"{marker}\")"),
+    (&["irb", "ruby"], "puts \"{marker}\""),
+    (&["nix"], "\"{marker}\""),
+    (&["R"], "cat(\"{marker}\\n\")"),
+    (&["psql"], "\\echo {marker}"),
+    (&["sqlite3"], "select '{marker}';"),
+];
+
+/// The preset template for a command, from the basename of its program
+pub fn preset(command: &[String]) -> Option<&'static str> {
+    let program = command.first()?;
+    let name = Path::new(program).file_name()?.to_str()?;
+    PRESETS.iter()
+        .find(|(names, _)| names.contains(&name))
+        .map(|(_, template)| *template)
+}
+
+/// Check that a template has somewhere to put the marker
+pub fn validate(template: &str) -> Result<()> {
+    if !template.contains(PLACEHOLDER) {
+        anyhow::bail!("sentinel template must contain {}: {}", PLACEHOLDER, template);
+    }
+    Ok(())
+}
+
+/// Read the stored sentinel template for a session, if any
+pub fn get_template(session: &str) -> Result<Option<String>> {
+    let path = session::session_path(session)?.join("sentinel");
+    match std::fs::read_to_string(&path) {
+        Ok(s) => Ok(Some(s.trim_end_matches('\n').to_string())),
+        Err(_) => Ok(None),
+    }
+}
+
+/// A sentinel completion with a fresh marker
+pub fn completion(template: &str) -> Completion {
+    let marker = format!("VIA-{}", nonce());
+    Completion::Sentinel { command: template.replace(PLACEHOLDER, &marker), marker }
+}
+
+/// A value unlikely to appear in output: the time in nanoseconds and our pid
+fn nonce() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("{:x}{:x}", nanos, std::process::id())
+}
+
+/// Whether a line of output (escape sequences removed) is the marker itself,
+/// possibly quoted the way a REPL shows a string value
+pub fn is_marker(line: &str, marker: &str) -> bool {
+    let line = line.trim();
+    line == marker
+        || line.strip_prefix('"').and_then(|l| l.strip_suffix('"')) == Some(marker)
+        || line.strip_prefix('\'').and_then(|l| l.strip_suffix('\'')) == Some(marker)
+}
+
+/// Drop the lines sentinels left in captured output: the terminal's echo of
+/// a command made from `template`, and the marker it printed
+pub fn strip_lines(output: &str, template: &str) -> String {
+    let command = regex::escape(template).replace(&regex::escape(PLACEHOLDER), "VIA-[0-9a-f]+");
+    let echo = Regex::new(&format!("{}\\s*$", command)).ok();
+    let marker = Regex::new(r#"^\s*["']?VIA-[0-9a-f]+["']?\s*$"#).ok();
+
+    output.split_inclusive('\n')
+        .filter(|line| {
+            let text = prompt::strip_ansi(line.trim_end_matches('\n').as_bytes());
+            // A line still being written is the prompt, not a sentinel's
+            !line.ends_with('\n')
+                || !(echo.as_ref().is_some_and(|r| r.is_match(&text))
+                     || marker.as_ref().is_some_and(|r| r.is_match(&text)))
+        })
+        .collect()
+}
@@ -1,43 +1,134 @@
 use anyhow::{Context, Result};
 use std::env;
 use std::fs;
-use std::path::PathBuf;
-
-/// Get the base directory for sessions, with fallback logic
+use std::io::Write;
+use std::ops::Range;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::budget;
+use crate::control;
+use crate::error::{self, Error};
+use crate::supervisor::{self, LaunchOptions};
+use crate::sentinel;
+use crate::shell;
+use crate::tail::{self, Completion};
+use crate::tombstone::{self, Tombstone};
+
+/// Get the base directory for sessions: `REPLS_DIR` if set, otherwise a
+/// per-user directory under `XDG_RUNTIME_DIR` or `/tmp`. The directory is
+/// created private to the current user and verified before use.
 pub fn base_dir() -> Result<PathBuf> {
-    let default_base = PathBuf::from("/run/via");
-
-    // Check REPLS_DIR environment variable first
     let base = if let Ok(repls_dir) = env::var("REPLS_DIR") {
         PathBuf::from(repls_dir)
+    } else if let Ok(xdg) = env::var("XDG_RUNTIME_DIR") {
+        PathBuf::from(xdg).join("via")
     } else {
-        default_base
+        PathBuf::from(format!("/tmp/via-{}", unsafe { libc::geteuid() }))
     };
 
-    // Try to create the directory if it doesn't exist
     if !base.exists() {
-        if fs::create_dir_all(&base).is_ok() {
-            return Ok(base);
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(dir_mode())
+            .create(&base)
+            .with_context(|| format!("can't create {}", base.display()))?;
+        // The umask may have masked out the group bits shared mode wants
+        fs::set_permissions(&base, fs::Permissions::from_mode(dir_mode()))?;
+    }
+
+    check_dir(&base)?;
+    Ok(base)
+}
+
+/// Whether group-shared mode is enabled (`VIA_SHARED=1`). Pair sessions can
+/// then live in a base dir shared by a group instead of a private one.
+pub fn shared_mode() -> bool {
+    env::var("VIA_SHARED").map(|v| v == "1").unwrap_or(false)
+}
+
+/// Permission bits for directories via creates
+fn dir_mode() -> u32 {
+    if shared_mode() { 0o2770 } else { 0o700 }
+}
+
+/// Verify that a directory is safe to use: a real directory (not a symlink),
+/// owned by the current user and not accessible to anyone else. In shared mode
+/// group access is fine, and so is a directory owned by another member of one
+/// of our groups; world access never is.
+fn check_dir(path: &Path) -> Result<()> {
+    let meta = fs::symlink_metadata(path)
+        .with_context(|| format!("can't access {}", path.display()))?;
+    if !meta.file_type().is_dir() {
+        anyhow::bail!("{} is not a directory", path.display());
+    }
+
+    let uid = unsafe { libc::geteuid() };
+    let mode = meta.mode() & 0o7777;
+
+    if shared_mode() {
+        if mode & 0o007 != 0 {
+            anyhow::bail!("{} is accessible to other users (mode {:o}); run: chmod o-rwx {}",
+                          path.display(), mode, path.display());
+        }
+        if meta.uid() != uid && !in_group(meta.gid()) {
+            anyhow::bail!("{} is owned by another user and not shared with any of your groups",
+                          path.display());
+        }
+    } else {
+        if meta.uid() != uid {
+            anyhow::bail!("{} is owned by uid {}, not you (set VIA_SHARED=1 for group-shared sessions)",
+                          path.display(), meta.uid());
+        }
+        if mode & 0o077 != 0 {
+            anyhow::bail!("{} is accessible to other users (mode {:o}); run: chmod 700 {}",
+                          path.display(), mode, path.display());
         }
+    }
 
-        // Fallback to XDG_RUNTIME_DIR or /tmp
-        let fallback = if let Ok(xdg) = env::var("XDG_RUNTIME_DIR") {
-            PathBuf::from(xdg).join("via")
-        } else {
-            PathBuf::from("/tmp/via")
-        };
+    Ok(())
+}
 
-        fs::create_dir_all(&fallback)
-            .with_context(|| format!("can't create {}", fallback.display()))?;
+/// Whether the current user is a member of `gid`
+fn in_group(gid: libc::gid_t) -> bool {
+    if unsafe { libc::getegid() } == gid {
+        return true;
+    }
 
-        return Ok(fallback);
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if count <= 0 {
+        return false;
     }
+    let mut groups = vec![0 as libc::gid_t; count as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    count > 0 && groups[..count as usize].contains(&gid)
+}
 
-    Ok(base)
+/// Open up a file teetty or the supervisor created so the group can use it in
+/// shared mode. Private sessions rely on the directory's permissions instead.
+pub fn share_with_group(path: &Path) -> Result<()> {
+    if shared_mode() {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o660))
+            .with_context(|| format!("failed to share {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Summary of a session, as shown in the listing
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub name: String,
+    pub command: Option<String>,
+    pub cwd: Option<String>,
+    pub group: Option<String>,
+    /// Last line of output, usually the prompt the session is waiting at
+    pub prompt: Option<String>,
+    /// How the session ended, if its command has exited
+    pub ended: Option<Tombstone>,
 }
 
-/// List all sessions (directories in base dir)
-pub fn list_sessions(simple: bool) -> Result<()> {
+/// List all sessions (directories in base dir), sorted by name
+pub fn list_sessions() -> Result<Vec<SessionInfo>> {
     let base = base_dir()?;
 
     let mut sessions = Vec::new();
@@ -49,80 +140,88 @@ pub fn list_sessions(simple: bool) -> Result<()> {
                     if let Some(name) = entry.file_name().to_str() {
                         let session_dir = entry.path();
 
+                        // Not a session name, e.g. an ended session being reclaimed
+                        if name.starts_with('.') {
+                            continue;
+                        }
+
+                        // Skip directories we wouldn't agree to use
+                        if check_dir(&session_dir).is_err() {
+                            continue;
+                        }
+
                         // Try to read metadata
                         let command = fs::read_to_string(session_dir.join("command"))
-                            .ok();
+                            .ok()
+                            .map(|s| s.trim().to_string());
                         let cwd = fs::read_to_string(session_dir.join("cwd"))
-                            .ok();
+                            .ok()
+                            .map(|s| s.trim().to_string());
+                        let group = fs::read_to_string(session_dir.join("group"))
+                            .ok()
+                            .map(|s| s.trim().to_string());
 
                         // Try to detect current prompt from stdout
                         let prompt = detect_prompt(&session_dir);
+                        let ended = tombstone::read_dir(&session_dir);
 
-                        sessions.push((name.to_string(), command, cwd, prompt));
+                        sessions.push(SessionInfo { name: name.to_string(), command, cwd, group, prompt, ended });
                     }
                 }
             }
         }
     }
 
-    sessions.sort_by(|a, b| a.0.cmp(&b.0));
+    sessions.sort_by(|a, b| a.name.cmp(&b.name));
 
-    if simple {
-        // Simple format: just session names
-        for (session, _, _, _) in sessions {
-            println!("{}", session);
-        }
-    } else {
-        // Table format
-        if sessions.is_empty() {
-            return Ok(());
-        }
-
-        // Calculate column widths
-        let max_session_len = sessions.iter()
-            .map(|(s, _, _, _)| s.len())
-            .max()
-            .unwrap_or(7)
-            .max(7); // "Session" header
-
-        let max_prompt_len = sessions.iter()
-            .map(|(_, _, _, p)| p.as_ref().map(|s| s.len()).unwrap_or(0))
-            .max()
-            .unwrap_or(11)
-            .max(11); // "Prompt Line" header
-
-        let max_cwd_len = sessions.iter()
-            .map(|(_, _, c, _)| c.as_ref().map(|s| s.trim().len()).unwrap_or(0))
-            .max()
-            .unwrap_or(17)
-            .max(17); // "Working Directory" header
-
-        // Print header
-        println!("{:<width_session$}  {:<width_prompt$}  {:<width_cwd$}  Command",
-                 "Session", "Prompt Line", "Working Directory",
-                 width_session = max_session_len,
-                 width_prompt = max_prompt_len,
-                 width_cwd = max_cwd_len);
-
-        // Print sessions
-        for (session, command, cwd, prompt) in sessions {
-            let prompt_str = prompt.as_deref().unwrap_or("");
-            let cwd_str = cwd.as_ref().map(|c| c.trim()).unwrap_or("");
-            let cmd_str = command.as_ref().map(|c| c.trim()).unwrap_or("");
-
-            println!("{:<width_session$}  {:<width_prompt$}  {:<width_cwd$}  {}",
-                     session, prompt_str, cwd_str, cmd_str,
-                     width_session = max_session_len,
-                     width_prompt = max_prompt_len,
-                     width_cwd = max_cwd_len);
-        }
+    Ok(sessions)
+}
+
+/// Running sessions selected by `pattern` (see `matches_pattern`)
+pub fn matching_sessions(pattern: &str) -> Result<Vec<SessionInfo>> {
+    Ok(list_sessions()?.into_iter()
+        .filter(|info| info.ended.is_none() && matches_pattern(info, pattern))
+        .collect())
+}
+
+/// Whether `pattern` selects a session: `@group` matches the sessions started
+/// with `--group group`, anything else is a glob on the name (`*` and `?`)
+pub fn matches_pattern(info: &SessionInfo, pattern: &str) -> bool {
+    match pattern.strip_prefix('@') {
+        Some(group) => info.group.as_deref() == Some(group),
+        None => glob_match(pattern, &info.name),
     }
+}
 
-    Ok(())
+/// Match `text` against a glob where `*` matches any run of characters and
+/// `?` matches exactly one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Iterative matching with backtracking to the last '*'
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
-/// Detect the current prompt from the stdout file (show last line, up to 20 chars)
-fn detect_prompt(session_dir: &std::path::Path) -> Option<String> {
+/// Detect the current prompt from the stdout file (the last non-empty line)
+fn detect_prompt(session_dir: &Path) -> Option<String> {
     let stdout_path = session_dir.join("stdout");
 
     // Read the last ~1KB of the file to find the prompt
@@ -145,11 +244,7 @@ fn detect_prompt(session_dir: &std::path::Path) -> Option<String> {
 
         // Get the last line
         let last_line = content.lines().last()?;
-
-        // Truncate to 20 characters if longer
-        if last_line.len() > 20 {
-            return Some(format!("{}...", &last_line[..20]));
-        } else if !last_line.is_empty() {
+        if !last_line.is_empty() {
             return Some(last_line.to_string());
         }
     }
@@ -157,10 +252,188 @@ fn detect_prompt(session_dir: &std::path::Path) -> Option<String> {
     None
 }
 
-/// Get the path for a specific session
+/// Check that a session name is safe to use as a directory under the base dir:
+/// 1-64 letters, digits, '.', '_', '-', '+' or '@', not starting with '.' or '-'.
+pub fn validate_name(name: &str) -> error::Result<()> {
+    let reason = if name.is_empty() {
+        Some("empty name")
+    } else if name.len() > 64 {
+        Some("longer than 64 characters")
+    } else if name.starts_with('.') || name.starts_with('-') {
+        Some("must not start with '.' or '-'")
+    } else if !name.chars().all(is_name_char) {
+        Some("use only letters, digits, '.', '_', '-', '+' or '@'")
+    } else {
+        None
+    };
+
+    match reason {
+        Some(reason) => Err(Error::InvalidName { name: name.to_string(), reason: reason.to_string() }),
+        None => Ok(()),
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+' | '@')
+}
+
+/// Turn arbitrary text (e.g. a directory name) into a valid session name
+fn sanitize_name(text: &str) -> String {
+    let name: String = text.chars()
+        .map(|c| if is_name_char(c) { c } else { '_' })
+        .collect();
+    let name = name.trim_start_matches(['.', '-']);
+    let name: String = name.chars().take(55).collect();
+    if name.is_empty() { "session".to_string() } else { name }
+}
+
+/// Atomically claim a session name by creating its directory. Fails if the
+/// name is invalid or another session already holds it, so two concurrent
+/// `via run` calls can never share a session. The tombstone of a session that
+/// has ended doesn't hold on to its name.
+pub fn claim(name: &str) -> error::Result<PathBuf> {
+    let dir = session_path(name)?;
+    loop {
+        match fs::DirBuilder::new().mode(dir_mode()).create(&dir) {
+            Ok(()) => {
+                // The umask may have masked out the group bits shared mode wants
+                fs::set_permissions(&dir, fs::Permissions::from_mode(dir_mode()))?;
+                return Ok(dir);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                if let Reclaim::Live = reclaim(&dir)? {
+                    return Err(Error::AlreadyExists(name.to_string()));
+                }
+            }
+            Err(err) => return Err(Error::Io(err)),
+        }
+    }
+}
+
+/// What `reclaim` found
+enum Reclaim {
+    /// The directory belongs to a live session (or a new one)
+    Live,
+    /// Another caller is clearing the directory away
+    Lost,
+    /// This caller removed the directory
+    Removed,
+}
+
+/// Clear away the directory of an ended session so its name can be claimed
+/// again.
+///
+/// Several callers may find the same tombstone. Renaming the tombstone away
+/// elects one of them: a live session never has one, so the winner knows the
+/// directory is dead and moves it aside before deleting it. A caller that
+/// loses the rename leaves the directory to the winner; `claim` then tries the
+/// name again, which only succeeds if nobody else has.
+fn reclaim(dir: &Path) -> error::Result<Reclaim> {
+    if tombstone::read_dir(dir).is_none() {
+        return Ok(Reclaim::Live);
+    }
+
+    let unique = format!("{}-{}", std::process::id(), crate::index::now_ms());
+    match fs::rename(dir.join("tombstone"), dir.join(format!("tombstone.reclaim-{}", unique))) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Reclaim::Lost),
+        Err(err) => return Err(Error::Io(err)),
+    }
+
+    let name = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let grave = dir.with_file_name(format!(".{}.reclaim-{}", name, unique));
+    fs::rename(dir, &grave)?;
+    fs::remove_dir_all(&grave)?;
+    Ok(Reclaim::Removed)
+}
+
+/// Remove an ended session's directory. Returns false, leaving it alone, if
+/// the session is live (its name may have just been reclaimed) or another
+/// caller is already removing it.
+pub fn remove_ended(name: &str) -> error::Result<bool> {
+    Ok(matches!(reclaim(&session_path(name)?)?, Reclaim::Removed))
+}
+
+/// Claim a generated session name like "cabal-00", "cabal-01" based on the command.
+pub fn claim_generated(command: &[String]) -> error::Result<String> {
+    let cmd_name = command.first()
+        .and_then(|cmd| Path::new(cmd).file_name().and_then(|n| n.to_str()))
+        .map(sanitize_name)
+        .unwrap_or_else(|| "session".to_string());
+
+    for index in 0u32..100 {
+        let name = format!("{}-{:02}", cmd_name, index);
+        match claim(&name) {
+            Ok(_) => return Ok(name),
+            Err(Error::AlreadyExists(_)) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(Error::Other(format!("too many sessions for '{}'", cmd_name)))
+}
+
+/// Resolve a session name, mapping `.` to a name for the current directory.
+/// Everything else is returned unchanged. This keeps `via` git-agnostic: the
+/// "session here" is derived from the working directory, in order of preference:
+///
+/// 1. the contents of a `.via-session` marker file in the cwd or an ancestor
+///    (an empty marker names the session after the marker's directory),
+/// 2. with `VIA_DOT=path`, the basename plus a hash of the full path, so two
+///    checkouts named `app` in different parents get different sessions,
+/// 3. otherwise the basename of the cwd.
+pub fn resolve_name(name: &str) -> Result<String> {
+    if name != "." {
+        return Ok(name.to_string());
+    }
+
+    let cwd = env::current_dir()?;
+
+    for dir in cwd.ancestors() {
+        let marker = dir.join(".via-session");
+        if let Ok(contents) = fs::read_to_string(&marker) {
+            let named = contents.trim();
+            if named.is_empty() {
+                return Ok(path_derived_name(dir));
+            }
+            validate_name(named)
+                .with_context(|| format!("bad session name in {}", marker.display()))?;
+            return Ok(named.to_string());
+        }
+    }
+
+    if env::var("VIA_DOT").map(|v| v == "path").unwrap_or(false) {
+        return Ok(path_derived_name(&cwd));
+    }
+
+    cwd.file_name()
+        .map(|s| sanitize_name(&s.to_string_lossy()))
+        .ok_or_else(|| anyhow::anyhow!("cannot resolve '.': cwd has no basename"))
+}
+
+/// A name like "app-1a2b3c4d": the directory's basename plus a hash of its full path
+fn path_derived_name(dir: &Path) -> String {
+    let base = dir.file_name()
+        .map(|s| sanitize_name(&s.to_string_lossy()))
+        .unwrap_or_else(|| "root".to_string());
+
+    // FNV-1a, which is stable across builds (unlike std's DefaultHasher)
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in dir.as_os_str().as_encoded_bytes() {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    format!("{}-{:08x}", base, hash)
+}
+
+/// Get the path for a specific session. An existing session directory is
+/// verified like the base dir, so a directory planted by another user is refused.
 pub fn session_path(session: &str) -> Result<PathBuf> {
-    let base = base_dir()?;
-    Ok(base.join(session))
+    validate_name(session)?;
+    let dir = base_dir()?.join(session);
+    if fs::symlink_metadata(&dir).is_ok() {
+        check_dir(&dir)?;
+    }
+    Ok(dir)
 }
 
 /// Get the stdin pipe path for a session
@@ -173,8 +446,12 @@ pub fn stdout_path(session: &str) -> Result<PathBuf> {
     Ok(session_path(session)?.join("stdout"))
 }
 
+/// Get the control socket path for a session
+pub fn control_path(session: &str) -> Result<PathBuf> {
+    Ok(session_path(session)?.join("control"))
+}
+
 /// Check if a session exists (has both stdin and stdout)
-#[allow(dead_code)]
 pub fn session_exists(session: &str) -> Result<bool> {
     let stdin = stdin_path(session)?;
     let stdout = stdout_path(session)?;
@@ -195,6 +472,76 @@ pub fn get_delim(session: &str) -> Result<Option<String>> {
     }
 }
 
+/// Store a delimiter as the session's default
+pub fn set_delim(session: &str, delim: &str) -> Result<()> {
+    let path = delim_path(session)?;
+    fs::write(&path, delim)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    share_with_group(&path)
+}
+
+/// Read the stored idle time (in milliseconds) for a session, if any
+pub fn get_idle(session: &str) -> Result<Option<u64>> {
+    let path = session_path(session)?.join("idle");
+    match fs::read_to_string(&path) {
+        Ok(s) => Ok(Some(s.trim().parse()
+            .with_context(|| format!("corrupt idle time {}", path.display()))?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// How to tell that a response is complete when no prompt is given: shell
+/// integration marks, else a fresh sentinel, else the stored delimiter, else
+/// the stored idle time
+pub fn default_completion(session: &str) -> error::Result<Completion> {
+    if shell::session_shell(session)?.is_some() {
+        return Ok(Completion::Shell);
+    }
+    if let Some(template) = sentinel::get_template(session)? {
+        return Ok(sentinel::completion(&template));
+    }
+    if let Some(delim) = get_delim(session)? {
+        return Ok(Completion::Prompt(delim));
+    }
+    if let Some(ms) = get_idle(session)? {
+        return Ok(Completion::Idle(ms));
+    }
+    Err(Error::NoDelim(session.to_string()))
+}
+
+/// Get the path of a named read cursor for a session
+pub fn cursor_path(session: &str, name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        anyhow::bail!("invalid cursor name: '{}'", name);
+    }
+    Ok(session_path(session)?.join("cursors").join(name))
+}
+
+/// Read the byte offset stored in a named cursor, if any
+pub fn get_cursor(session: &str, name: &str) -> Result<Option<u64>> {
+    let path = cursor_path(session, name)?;
+    match fs::read_to_string(&path) {
+        Ok(s) => Ok(Some(s.trim().parse()
+            .with_context(|| format!("corrupt cursor {}", path.display()))?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Store a byte offset in a named cursor. Written via rename so concurrent
+/// readers never see a partially written value.
+pub fn set_cursor(session: &str, name: &str, offset: u64) -> Result<()> {
+    let path = cursor_path(session, name)?;
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create {}", dir.display()))?;
+    let tmp = dir.join(format!(".{}.tmp", name));
+    fs::write(&tmp, offset.to_string())
+        .with_context(|| format!("failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, &path)
+        .with_context(|| format!("failed to update cursor {}", path.display()))?;
+    Ok(())
+}
+
 /// Resolve a flag value: if the next arg exists and doesn't start with "--",
 /// use it as the explicit value. Otherwise fall back to the stored session delim.
 /// Returns (value, number_of_args_consumed).
@@ -214,3 +561,229 @@ pub fn resolve_delim(session: &str, args: &[String], i: usize) -> Result<(String
         Ok((next.unwrap().clone(), 2))
     }
 }
+
+/// Output read from a session, with the byte range it covers in the stdout file
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub text: String,
+    pub offset: u64,
+    pub end: u64,
+    /// The byte range in the stdout file behind each line of `text`
+    pub lines: Vec<Range<u64>>,
+    /// Exit status of the command, for sessions with shell integration
+    pub status: Option<i32>,
+}
+
+/// A handle to a named session.
+///
+/// Requests go through the session's control socket when its supervisor is
+/// listening, and fall back to the FIFO and stdout file otherwise.
+#[derive(Debug, Clone)]
+pub struct Session {
+    name: String,
+    dir: PathBuf,
+}
+
+impl Session {
+    /// Open an existing session
+    pub fn open(name: &str) -> error::Result<Session> {
+        let session = Session::find(name)?;
+        tombstone::check_live(name)?;
+        Ok(session)
+    }
+
+    /// Open an existing session even if it has ended, to read its transcript
+    pub fn find(name: &str) -> error::Result<Session> {
+        let dir = session_path(name)?;
+        if !dir.exists() {
+            return Err(Error::NotFound(name.to_string()));
+        }
+        Ok(Session { name: name.to_string(), dir })
+    }
+
+    /// Start `command` as a new background session. Without a name, one is
+    /// generated from the command (e.g. "python3-00"). The session is launched
+    /// by a `via` process of its own, so the `via` binary must be installed.
+    pub fn spawn(name: Option<&str>, command: &[String], options: &LaunchOptions) -> error::Result<Session> {
+        let name = match name {
+            Some(name) => {
+                claim(name)?;
+                name.to_string()
+            }
+            None => claim_generated(command)?,
+        };
+        supervisor::spawn(&name, command, options)?;
+        Session::open(&name)
+    }
+
+    /// List all sessions
+    pub fn list() -> error::Result<Vec<SessionInfo>> {
+        Ok(list_sessions()?)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The session directory holding the FIFO, output file and metadata
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The stored delimiter, if the session was started with one
+    pub fn delim(&self) -> error::Result<Option<String>> {
+        Ok(get_delim(&self.name)?)
+    }
+
+    /// Write a line of input without waiting for a response
+    pub fn send(&self, input: &str) -> error::Result<()> {
+        let request = serde_json::json!({ "op": "send", "input": input });
+        if control::request(&self.name, &request)?.is_none() {
+            crate::fifo::write_input(&self.name, input)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the session is at its prompt, send `input` and return the
+    /// output up to the next prompt. Uses the stored delimiter (or idle time)
+    /// if `delim` is `None`.
+    pub fn send_and_wait(&self, input: &str, delim: Option<&str>, timeout_secs: f64) -> error::Result<Output> {
+        let completion = self.resolve(delim)?;
+        self.send_and_wait_until(input, &completion, timeout_secs)
+    }
+
+    /// Send `input` and return the output until `completion` is met
+    pub fn send_and_wait_until(&self, input: &str, completion: &Completion, timeout_secs: f64) -> error::Result<Output> {
+        self.send_and_stream(input, completion, timeout_secs, &mut std::io::sink())
+    }
+
+    /// Like `send_and_wait_until`, but also writes the output to `writer` a
+    /// line at a time as it arrives
+    pub fn send_and_stream(&self, input: &str, completion: &Completion, timeout_secs: f64, writer: &mut dyn Write) -> error::Result<Output> {
+        let mut request = serde_json::json!({
+            "op": "send_and_wait",
+            "input": input,
+            "timeout": timeout_secs,
+            "stream": true,
+        });
+        completion_fields(&mut request, "delim", completion);
+        let mut output = match control::request_streaming(&self.name, &request, writer)? {
+            Some(response) => output_from_response(&response),
+            None => {
+                let mut tee = Tee { text: Vec::new(), writer };
+                let mut lines = Vec::new();
+                let (offset, end) = tail::send_and_follow(&self.name, input, completion, timeout_secs, &mut tee, &mut lines)?;
+                Output { text: String::from_utf8_lossy(&tee.text).into_owned(), offset, end, lines, status: None }
+            }
+        };
+
+        if let Completion::Shell = completion {
+            output.status = shell::exit_status(&self.name, output.offset, output.end)?;
+        }
+        Ok(output)
+    }
+
+    /// Wait until `pattern` (or the stored delimiter) appears anywhere in the
+    /// output, and return the output up to and including that line.
+    pub fn wait_for(&self, pattern: Option<&str>, timeout_secs: f64) -> error::Result<Output> {
+        let completion = self.resolve(pattern)?;
+        self.wait_until(&completion, timeout_secs)
+    }
+
+    /// Wait until `completion` is met, reading from the start of the output
+    pub fn wait_until(&self, completion: &Completion, timeout_secs: f64) -> error::Result<Output> {
+        let mut request = serde_json::json!({ "op": "wait", "timeout": timeout_secs });
+        completion_fields(&mut request, "until", completion);
+        if let Some(response) = control::request(&self.name, &request)? {
+            return Ok(output_from_response(&response));
+        }
+
+        let mut text = Vec::new();
+        let mut lines = Vec::new();
+        let end = tail::wait(&self.name, completion, timeout_secs, 0, &mut text, &mut lines)?;
+        Ok(Output { text: String::from_utf8_lossy(&text).into_owned(), offset: 0, end, lines, status: None })
+    }
+
+    /// Everything written since byte `offset`. Pass the returned `end` as the
+    /// next offset to read incrementally.
+    pub fn read_since(&self, offset: u64) -> error::Result<Output> {
+        let (offset, data, end) = tail::read_from_offset(&self.name, offset)?;
+        let lines = budget::raw_lines(&data, offset);
+        Ok(Output { text: String::from_utf8_lossy(&data).into_owned(), offset, end, lines, status: None })
+    }
+
+    /// Terminate the session's teetty process and wait up to `timeout_secs`
+    /// for its supervisor to leave a tombstone (or remove the session). A
+    /// session with a restart policy is not restarted.
+    pub fn stop(&self, timeout_secs: f64) -> error::Result<()> {
+        let pid: libc::pid_t = fs::read_to_string(self.dir.join("pid"))
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .ok_or_else(|| Error::Other(format!("session '{}' has no recorded process", self.name)))?;
+        fs::write(self.dir.join("stopped"), "")?;
+
+        if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+            return Err(Error::Io(std::io::Error::last_os_error()));
+        }
+
+        let deadline = tail::deadline(timeout_secs)?;
+        while self.dir.exists() && tombstone::read_dir(&self.dir).is_none() {
+            if std::time::Instant::now() > deadline {
+                return Err(Error::Timeout { pattern: "session exit".to_string(), secs: timeout_secs });
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, delim: Option<&str>) -> error::Result<Completion> {
+        match delim {
+            Some(d) => Ok(Completion::Prompt(d.to_string())),
+            None => default_completion(&self.name),
+        }
+    }
+}
+
+/// Describe a completion in a control request: the prompt under `prompt_key`,
+/// the idle time under "idle", `"shell": true` for shell integration marks, or
+/// the sentinel command and marker under "sentinel"
+fn completion_fields(request: &mut serde_json::Value, prompt_key: &str, completion: &Completion) {
+    match completion {
+        Completion::Prompt(prompt) => request[prompt_key] = serde_json::json!(prompt),
+        Completion::Idle(ms) => request["idle"] = serde_json::json!(ms),
+        Completion::Shell => request["shell"] = serde_json::json!(true),
+        Completion::Sentinel { command, marker } => {
+            request["sentinel"] = serde_json::json!({ "command": command, "marker": marker })
+        }
+    }
+}
+
+/// Keeps a copy of the output while passing it on
+struct Tee<'a> {
+    text: Vec<u8>,
+    writer: &'a mut dyn Write,
+}
+
+impl Write for Tee<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.text.extend_from_slice(buf);
+        self.writer.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn output_from_response(response: &serde_json::Value) -> Output {
+    Output {
+        text: response["output"].as_str().unwrap_or("").to_string(),
+        offset: response["offset"].as_u64().unwrap_or(0),
+        end: response["end"].as_u64().unwrap_or(0),
+        lines: response["lines"].as_array().map(|lines| lines.iter()
+            .filter_map(|r| Some(r[0].as_u64()?..r[1].as_u64()?))
+            .collect()).unwrap_or_default(),
+        status: None,
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::prompt;
+use crate::session;
+
+// Shell integration: `via run --shell` starts bash, zsh or fish with a startup
+// script that emits OSC 133 semantic prompt marks around every command:
+//
+//   ESC ] 133 ; A BEL        prompt starts
+//   ESC ] 133 ; B BEL        prompt ends, command line input starts
+//   ESC ] 133 ; C BEL        command output starts
+//   ESC ] 133 ; D ; N BEL    command finished with exit status N
+//
+// The output of a command is the text between its C and D marks, so no prompt
+// text is needed to delimit it.
+
+/// Start of every OSC 133 mark
+pub const MARK: &[u8] = b"\x1b]133;";
+
+/// Shells `via run --shell` knows how to integrate with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    /// Recognize the shell a command runs, from the basename of its program
+    pub fn from_command(command: &[String]) -> Option<Shell> {
+        let program = command.first()?;
+        let name = Path::new(program).file_name()?.to_str()?;
+        Shell::from_name(name)
+    }
+
+    fn from_name(name: &str) -> Option<Shell> {
+        match name {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+        }
+    }
+}
+
+const BASH_INTEGRATION: &str = r#"# via shell integration: OSC 133 semantic prompt marks
+[ -f ~/.bashrc ] && . ~/.bashrc
+__via_prompt() {
+    local status=$?
+    printf '\033]133;D;%s\007\033]133;A\007' "$status"
+    return $status
+}
+PROMPT_COMMAND="__via_prompt${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+PS1="$PS1\[\033]133;B\007\]"
+PS0="$PS0\033]133;C\007"
+"#;
+
+const ZSH_ENV: &str = r#"# via shell integration: load the user's own .zshenv
+[ -f "${VIA_ORIG_ZDOTDIR:-$HOME}/.zshenv" ] && . "${VIA_ORIG_ZDOTDIR:-$HOME}/.zshenv"
+"#;
+
+const ZSH_INTEGRATION: &str = r#"# via shell integration: OSC 133 semantic prompt marks
+ZDOTDIR="${VIA_ORIG_ZDOTDIR:-$HOME}"
+unset VIA_ORIG_ZDOTDIR
+[ -f "$ZDOTDIR/.zshrc" ] && . "$ZDOTDIR/.zshrc"
+__via_precmd() { printf '\033]133;D;%s\007\033]133;A\007' "$?" }
+__via_preexec() { printf '\033]133;C\007' }
+precmd_functions=(__via_precmd $precmd_functions)
+preexec_functions+=(__via_preexec)
+PS1="$PS1%{$(printf '\033]133;B\007')%}"
+"#;
+
+const FISH_INTEGRATION: &str = r#"# via shell integration: OSC 133 semantic prompt marks
+set -g __via_status 0
+function __via_preexec --on-event fish_preexec
+    printf '\e]133;C\a'
+end
+function __via_postexec --on-event fish_postexec
+    set -g __via_status $status
+end
+function __via_prompt --on-event fish_prompt
+    printf '\e]133;D;%s\a\e]133;A\a' $__via_status
+end
+"#;
+
+/// A shell command rewritten to load the integration script
+pub struct Integration {
+    pub command: Vec<String>,
+    /// Extra environment variables for the shell
+    pub env: Vec<(String, String)>,
+}
+
+/// Write the integration script for `shell` into the session directory and
+/// return the command (and extra environment) that loads it
+pub fn integrate(shell: Shell, dir: &Path, command: &[String]) -> Result<Integration> {
+    let script_dir = dir.join("integration");
+    fs::create_dir_all(&script_dir)
+        .with_context(|| format!("failed to create {}", script_dir.display()))?;
+
+    let mut command = command.to_vec();
+    let mut env = Vec::new();
+    match shell {
+        Shell::Bash => {
+            let rc = script_dir.join("bashrc");
+            fs::write(&rc, BASH_INTEGRATION).with_context(|| "failed to write bash integration")?;
+            command.splice(1..1, ["--rcfile".to_string(), rc.to_string_lossy().into_owned()]);
+        }
+        Shell::Zsh => {
+            fs::write(script_dir.join(".zshenv"), ZSH_ENV).with_context(|| "failed to write zsh integration")?;
+            fs::write(script_dir.join(".zshrc"), ZSH_INTEGRATION).with_context(|| "failed to write zsh integration")?;
+            if let Ok(zdotdir) = std::env::var("ZDOTDIR") {
+                env.push(("VIA_ORIG_ZDOTDIR".to_string(), zdotdir));
+            }
+            env.push(("ZDOTDIR".to_string(), script_dir.to_string_lossy().into_owned()));
+        }
+        Shell::Fish => {
+            let init = script_dir.join("init.fish");
+            fs::write(&init, FISH_INTEGRATION).with_context(|| "failed to write fish integration")?;
+            command.splice(1..1, ["--init-command".to_string(), format!("source '{}'", init.display())]);
+        }
+    }
+
+    Ok(Integration { command, env })
+}
+
+/// The shell a session was started with `--shell` for, if any
+pub fn session_shell(session: &str) -> Result<Option<Shell>> {
+    let path = session::session_path(session)?.join("shell");
+    match fs::read_to_string(&path) {
+        Ok(name) => Ok(Shell::from_name(name.trim())),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Check that the shell is at its prompt: the last mark it wrote is a prompt
+/// mark (A or B) rather than a running command's C
+pub fn check_ready(session: &str) -> Result<()> {
+    let stdout_path = session::stdout_path(session)?;
+    let mut file = File::open(&stdout_path)
+        .with_context(|| format!("no stdout at {} (is the session running?)", stdout_path.display()))?;
+
+    let file_size = file.seek(SeekFrom::End(0))?;
+    let start = file_size.saturating_sub(4096);
+    file.seek(SeekFrom::Start(start))?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+
+    match last_mark(&buffer) {
+        Some(b'A') | Some(b'B') => Ok(()),
+        _ => Err(Error::NotReady {
+            prompt: "shell prompt".to_string(),
+            found: prompt::last_line(session)?,
+        }.into()),
+    }
+}
+
+/// Where the first mark of the given kind in `data` starts and ends
+pub fn find_mark(data: &[u8], kind: u8) -> Option<(usize, usize)> {
+    let mut from = 0;
+    while let Some(i) = find(&data[from..], MARK) {
+        let at = from + i;
+        let kind_at = at + MARK.len();
+        if data.get(kind_at) == Some(&kind) {
+            // The mark runs to BEL or ST (ESC \)
+            let mut end = kind_at;
+            while end < data.len() && data[end] != 0x07 && data[end] != 0x1b {
+                end += 1;
+            }
+            let end = match data.get(end) {
+                Some(0x07) => end + 1,
+                Some(0x1b) => end + 2,
+                _ => end,
+            };
+            return Some((at, end.min(data.len())));
+        }
+        from = kind_at;
+    }
+    None
+}
+
+/// The kind letter of the last mark in `data`
+fn last_mark(data: &[u8]) -> Option<u8> {
+    let mut last = None;
+    let mut from = 0;
+    while let Some(i) = find(&data[from..], MARK) {
+        let kind_at = from + i + MARK.len();
+        last = data.get(kind_at).copied().or(last);
+        from = kind_at;
+    }
+    last
+}
+
+/// The exit status reported by the last D mark between `start` and `end` in
+/// the session's output
+pub fn exit_status(session: &str, start: u64, end: u64) -> Result<Option<i32>> {
+    let stdout_path = session::stdout_path(session)?;
+    let mut file = File::open(&stdout_path)
+        .with_context(|| format!("no stdout at {} (is the session running?)", stdout_path.display()))?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut data = Vec::new();
+    file.take(end.saturating_sub(start)).read_to_end(&mut data)?;
+
+    let mut status = None;
+    let mut from = 0;
+    while let Some((at, mark_end)) = find_mark(&data[from..], b'D') {
+        let mark = &data[from + at + MARK.len()..from + mark_end];
+        status = String::from_utf8_lossy(mark)
+            .trim_start_matches("D;")
+            .trim_end_matches(['\x07', '\x1b', '\\'])
+            .parse()
+            .ok()
+            .or(status);
+        from += mark_end;
+    }
+    Ok(status)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
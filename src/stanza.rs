@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+
+use crate::prompt;
+use crate::session;
+
+/// One prompt-delimited exchange in a session's output: the prompt line
+/// (which echoes the input) followed by the output, up to the next prompt.
+#[derive(Debug, Clone)]
+pub struct Stanza {
+    /// Byte offset of the prompt line that starts the stanza
+    pub start: u64,
+    /// Byte offset just past the prompt line, where the output starts
+    pub body: u64,
+    /// Byte offset of the next prompt line, or the end of the output
+    pub end: u64,
+    /// Input echoed after the prompt, with escape sequences removed
+    pub input: String,
+}
+
+/// Split output into stanzas at every line containing `prompt`. Returns the
+/// byte offset where the first stanza starts (everything before it is the
+/// startup banner) and the stanzas themselves.
+pub fn split(data: &[u8], prompt: &str) -> (u64, Vec<Stanza>) {
+    let mut stanzas: Vec<Stanza> = Vec::new();
+    let mut pos = 0usize;
+
+    while pos < data.len() {
+        let line_end = match data[pos..].iter().position(|&b| b == b'\n') {
+            Some(i) => pos + i + 1,
+            None => data.len(),
+        };
+
+        if let Some(input) = prompt_input(&data[pos..line_end], prompt) {
+            if let Some(last) = stanzas.last_mut() {
+                last.end = pos as u64;
+            }
+            stanzas.push(Stanza {
+                start: pos as u64,
+                body: line_end as u64,
+                end: data.len() as u64,
+                input,
+            });
+        }
+
+        pos = line_end;
+    }
+
+    let preamble_end = stanzas.first().map(|s| s.start).unwrap_or(data.len() as u64);
+    (preamble_end, stanzas)
+}
+
+impl Stanza {
+    /// A prompt still waiting for input: nothing typed after it, nothing printed
+    pub fn is_pending(&self) -> bool {
+        self.input.is_empty() && self.body >= self.end
+    }
+}
+
+/// A session's finished stanzas, numbered from 1 in output order. The prompt
+/// waiting for input at the end is left out, so -1 is the last exchange.
+pub fn numbered(data: &[u8], prompt: &str) -> Vec<Stanza> {
+    let (_, mut stanzas) = split(data, prompt);
+    if stanzas.last().is_some_and(Stanza::is_pending) {
+        stanzas.pop();
+    }
+    stanzas
+}
+
+/// Look up stanza `n` (from 1), or counting back from the last with negative
+/// numbers (-1 is the last)
+pub fn nth(stanzas: &[Stanza], n: i64) -> Option<&Stanza> {
+    let index = if n > 0 {
+        n - 1
+    } else if n < 0 {
+        stanzas.len() as i64 + n
+    } else {
+        return None;
+    };
+    usize::try_from(index).ok().and_then(|i| stanzas.get(i))
+}
+
+/// If `line` contains the prompt, the input echoed after it
+fn prompt_input(line: &[u8], prompt: &str) -> Option<String> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let text = prompt::process_terminal_output(line).ok()?;
+    let at = text.rfind(prompt)?;
+    Some(text[at + prompt.len()..].trim().to_string())
+}
+
+/// Read a session's whole output file
+pub fn read_output(session: &str) -> Result<Vec<u8>> {
+    let stdout_path = session::stdout_path(session)?;
+    std::fs::read(&stdout_path)
+        .with_context(|| format!("no stdout at {} (is the session running?)", stdout_path.display()))
+}
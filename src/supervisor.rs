@@ -0,0 +1,497 @@
+use anyhow::{Context, Result};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::control;
+use crate::hooks::{self, Event, Hook};
+use crate::index;
+use crate::session;
+use crate::shell::{self, Shell};
+use crate::tombstone;
+
+/// Options for launching a session
+#[derive(Debug, Clone, Default)]
+pub struct LaunchOptions {
+    /// Prompt stored as the session's default delimiter
+    pub delim: Option<String>,
+    /// Group the session belongs to, for addressing several sessions at once
+    pub group: Option<String>,
+    /// Silence (in milliseconds) that marks a response as complete, for
+    /// programs without a reliable prompt
+    pub idle: Option<u64>,
+    /// Install shell integration (bash, zsh or fish) so command output and
+    /// exit status are delimited by OSC 133 marks
+    pub shell: bool,
+    /// Sentinel template (containing `{marker}`) sent after each input to
+    /// mark the end of its output
+    pub sentinel: Option<String>,
+    /// Patterns that mark a command's output as failed in lockstep runs,
+    /// replacing the preset for the program
+    pub errors: Vec<String>,
+    /// Whether to relaunch the command when it exits
+    pub restart: Restart,
+    /// Give up after this many restarts (unlimited if None)
+    pub max_restarts: Option<u32>,
+    /// Delay in milliseconds before the first restart, doubled for each
+    /// one after it (default: `DEFAULT_BACKOFF_MS`)
+    pub backoff: Option<u64>,
+    /// Remove the session directory when the command exits instead of
+    /// leaving a tombstone
+    pub remove: bool,
+    /// Hooks written `EVENT=COMMAND`, run in addition to the user's
+    pub hooks: Vec<String>,
+    /// Directory to run the command in (default: the current directory)
+    pub cwd: Option<PathBuf>,
+    /// Variables to set in the command's environment
+    pub env: Vec<(String, String)>,
+    /// Start the command with only `env` (and via's own variables) instead of
+    /// inheriting the environment
+    pub clear_env: bool,
+}
+
+impl LaunchOptions {
+    /// The `via run` flags that reproduce these options, for handing them to
+    /// another via process
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut flag = |name: &str, value: Option<String>| {
+            args.push(name.to_string());
+            args.extend(value);
+        };
+        if let Some(ref delim) = self.delim {
+            flag("--delim", Some(delim.clone()));
+        }
+        if let Some(ref group) = self.group {
+            flag("--group", Some(group.clone()));
+        }
+        if let Some(idle) = self.idle {
+            flag("--idle", Some(idle.to_string()));
+        }
+        if self.shell {
+            flag("--shell", None);
+        }
+        if let Some(ref template) = self.sentinel {
+            flag("--sentinel", Some(template.clone()));
+        }
+        for pattern in &self.errors {
+            flag("--error-pattern", Some(pattern.clone()));
+        }
+        match self.restart {
+            Restart::Never => {}
+            Restart::OnFailure => flag("--restart", Some("on-failure".to_string())),
+            Restart::Always => flag("--restart", Some("always".to_string())),
+        }
+        if let Some(max) = self.max_restarts {
+            flag("--max-restarts", Some(max.to_string()));
+        }
+        if let Some(backoff) = self.backoff {
+            flag("--backoff", Some(backoff.to_string()));
+        }
+        if self.remove {
+            flag("--rm", None);
+        }
+        for hook in &self.hooks {
+            flag("--hook", Some(hook.clone()));
+        }
+        if let Some(ref cwd) = self.cwd {
+            flag("--cwd", Some(cwd.to_string_lossy().into_owned()));
+        }
+        for (key, value) in &self.env {
+            flag("--env", Some(format!("{}={}", key, value)));
+        }
+        if self.clear_env {
+            flag("--clear-env", None);
+        }
+        args
+    }
+}
+
+/// Parse an environment variable written `KEY=VALUE`
+pub fn parse_env(spec: &str) -> Result<(String, String)> {
+    let (key, value) = spec.split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("invalid variable: {} (use KEY=VALUE)", spec))?;
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        anyhow::bail!("invalid variable name: '{}'", key);
+    }
+    if value.contains('\n') {
+        anyhow::bail!("the value of {} contains a newline", key);
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Read variables from a file of `KEY=VALUE` lines. Blank lines, `#` comments
+/// and a leading `export` are skipped, and quotes around a value are removed.
+pub fn read_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("can't read {}", path.display()))?;
+    let mut vars = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (key, value) = parse_env(line).with_context(|| format!("in {}", path.display()))?;
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|&q| value.strip_prefix(q).and_then(|v| v.strip_suffix(q)))
+            .map(String::from)
+            .unwrap_or(value);
+        vars.push((key, value));
+    }
+    Ok(vars)
+}
+
+/// Delay before the first restart when no backoff is given
+pub const DEFAULT_BACKOFF_MS: u64 = 1000;
+
+/// Longest delay between restarts, however many there have been
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// When a session's command is relaunched after it exits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Restart {
+    /// Let the session end with its command
+    #[default]
+    Never,
+    /// Relaunch when the command exits with a non-zero status or a signal
+    OnFailure,
+    /// Relaunch whenever the command exits
+    Always,
+}
+
+impl Restart {
+    /// Parse a policy: `never`, `on-failure` or `always`
+    pub fn parse(text: &str) -> Result<Restart> {
+        match text {
+            "never" | "no" => Ok(Restart::Never),
+            "on-failure" => Ok(Restart::OnFailure),
+            "always" => Ok(Restart::Always),
+            _ => anyhow::bail!("invalid restart policy: {} (use on-failure or always)", text),
+        }
+    }
+
+    fn applies(self, status: &ExitStatus) -> bool {
+        match self {
+            Restart::Never => false,
+            Restart::OnFailure => !status.success(),
+            Restart::Always => true,
+        }
+    }
+}
+
+/// Write the metadata files into a session directory claimed with `session::claim`
+fn prepare(session: &str, command: &[String], options: &LaunchOptions) -> Result<PathBuf> {
+    if command.is_empty() {
+        anyhow::bail!("no command given for session '{}'", session);
+    }
+
+    let dir = session::session_path(session)?;
+    if !dir.is_dir() {
+        anyhow::bail!("session '{}' has not been claimed", session);
+    }
+
+    std::fs::write(dir.join("command"), command.join(" "))
+        .with_context(|| "failed to write command metadata")?;
+
+    if let Some(ref d) = options.delim {
+        std::fs::write(dir.join("delim"), d)
+            .with_context(|| "failed to write delim metadata")?;
+    }
+
+    if options.shell {
+        let shell = Shell::from_command(command)
+            .ok_or_else(|| anyhow::anyhow!("--shell supports bash, zsh and fish, not '{}'", command[0]))?;
+        std::fs::write(dir.join("shell"), shell.name())
+            .with_context(|| "failed to write shell metadata")?;
+    }
+
+    if let Some(ref template) = options.sentinel {
+        std::fs::write(dir.join("sentinel"), template)
+            .with_context(|| "failed to write sentinel metadata")?;
+    }
+
+    if !options.errors.is_empty() {
+        std::fs::write(dir.join("errors"), options.errors.join("\n") + "\n")
+            .with_context(|| "failed to write error pattern metadata")?;
+    }
+
+    if !options.hooks.is_empty() {
+        std::fs::write(dir.join("hooks"), options.hooks.join("\n") + "\n")
+            .with_context(|| "failed to write hooks metadata")?;
+    }
+
+    if let Some(idle) = options.idle {
+        std::fs::write(dir.join("idle"), idle.to_string())
+            .with_context(|| "failed to write idle metadata")?;
+    }
+
+    if let Some(ref group) = options.group {
+        std::fs::write(dir.join("group"), group)
+            .with_context(|| "failed to write group metadata")?;
+    }
+
+    if let Some(cwd) = options.cwd.clone().or_else(|| env::current_dir().ok()) {
+        std::fs::write(dir.join("cwd"), cwd.to_string_lossy().as_bytes())
+            .with_context(|| "failed to write cwd metadata")?;
+    }
+
+    if !options.env.is_empty() {
+        let lines: Vec<String> = options.env.iter().map(|(k, v)| format!("{}={}\n", k, v)).collect();
+        std::fs::write(dir.join("env"), lines.concat())
+            .with_context(|| "failed to write env metadata")?;
+    }
+
+    if options.clear_env {
+        std::fs::write(dir.join("clear-env"), "")
+            .with_context(|| "failed to write env metadata")?;
+    }
+
+    Ok(dir)
+}
+
+/// Build the teetty command that runs `command` behind the session's FIFO and
+/// output file. A restarted command appends to the output instead of truncating it.
+fn teetty_command(dir: &Path, command: &[String], options: &LaunchOptions, truncate: bool) -> Result<Command> {
+    // A cleared environment has no PATH to find teetty with
+    let mut cmd = if options.clear_env {
+        Command::new(find_program("teetty"))
+    } else {
+        Command::new("teetty")
+    };
+    cmd.arg("-i").arg(dir.join("stdin"))
+       .arg("-o").arg(dir.join("stdout"));
+    if truncate {
+        cmd.arg("--truncate");
+    }
+    cmd.arg("--");
+
+    if options.clear_env {
+        cmd.env_clear();
+    }
+    cmd.envs(options.env.iter().map(|(k, v)| (k, v)));
+    if let Some(name) = dir.file_name() {
+        cmd.env("VIA_SESSION", name);
+    }
+    cmd.env("VIA_SESSION_DIR", dir);
+    if let Some(ref cwd) = options.cwd {
+        cmd.current_dir(cwd);
+    }
+
+    // Load the integration script into shells started with --shell
+    let mut command = command.to_vec();
+    if let Some(shell) = options.shell.then(|| Shell::from_command(&command)).flatten() {
+        let integration = shell::integrate(shell, dir, &command)?;
+        command = integration.command;
+        cmd.envs(integration.env);
+    }
+
+    // Add the command and args
+    for arg in command {
+        cmd.arg(arg);
+    }
+
+    Ok(cmd)
+}
+
+/// The full path of `program` on our PATH, or just its name if it isn't found
+fn find_program(program: &str) -> PathBuf {
+    env::var_os("PATH")
+        .and_then(|path| env::split_paths(&path).map(|dir| dir.join(program)).find(|p| p.is_file()))
+        .unwrap_or_else(|| PathBuf::from(program))
+}
+
+/// Run teetty with `cmd`, relaunching the command as the restart policy allows
+/// until it exits for good, and run the start and exit hooks for each launch.
+/// Each restart is appended to the session's `restarts` file; the name, FIFO
+/// and output file stay the same throughout. Returns the last exit status.
+fn supervise(dir: &Path, command: &[String], options: &LaunchOptions, hooks: &[Hook], mut cmd: Command) -> Result<ExitStatus> {
+    let mut restarts = 0;
+    loop {
+        let mut child = cmd.spawn()
+            .with_context(|| "failed to execute teetty (is it installed?)")?;
+        std::fs::write(dir.join("pid"), child.id().to_string())
+            .with_context(|| "failed to write pid metadata")?;
+        hooks::fire(hooks, dir, &Event::Start);
+        let status = child.wait()
+            .with_context(|| "failed to wait for teetty")?;
+        hooks::fire_exit(hooks, dir, &status);
+
+        // A session stopped on purpose stays stopped
+        let stopped = !dir.is_dir() || dir.join("stopped").exists();
+        let exhausted = options.max_restarts.is_some_and(|max| restarts >= max);
+        if stopped || exhausted || !options.restart.applies(&status) {
+            return Ok(status);
+        }
+
+        restarts += 1;
+        record_restart(dir, &status)?;
+        let backoff = Duration::from_millis(options.backoff.unwrap_or(DEFAULT_BACKOFF_MS));
+        thread::sleep(backoff.saturating_mul(1u32 << (restarts - 1).min(16)).min(MAX_BACKOFF));
+        cmd = teetty_command(dir, command, options, false)?;
+    }
+}
+
+/// Append a line to the `restarts` file: the time (Unix seconds) and how the
+/// command exited, e.g. `1718000000 exit 1` or `1718000000 signal 9`
+fn record_restart(dir: &Path, status: &ExitStatus) -> Result<()> {
+    use std::io::Write;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let how = match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exit {}", code),
+        (None, Some(signal)) => format!("signal {}", signal),
+        (None, None) => "unknown".to_string(),
+    };
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(dir.join("restarts"))
+        .with_context(|| "failed to open restarts file")?;
+    writeln!(file, "{} {}", now, how).with_context(|| "failed to record restart")
+}
+
+/// Launch a claimed session in the background from a separate `via __supervise`
+/// process, so the caller is never forked (forking a multithreaded process is only
+/// safe if the child does nothing but exec). That process runs [`spawn_forked`] and
+/// exits once the session is up; the supervisor it forked carries on detached.
+/// The `via` binary is the current executable when that is via itself, and
+/// otherwise the one on PATH.
+pub fn spawn(session: &str, command: &[String], options: &LaunchOptions) -> Result<()> {
+    let exe = env::current_exe().ok()
+        .filter(|exe| exe.file_name().is_some_and(|name| name == "via"))
+        .unwrap_or_else(|| find_program("via"));
+    let output = Command::new(&exe)
+        .arg("__supervise").arg(session)
+        .args(options.to_args())
+        .arg("--").args(command)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("failed to run {}", exe.display()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr.trim().trim_start_matches("via: ");
+        anyhow::bail!("failed to launch session '{}': {}", session, message);
+    }
+    Ok(())
+}
+
+/// Launch a claimed session in the background by forking this process. The forked
+/// supervisor detaches from the terminal, serves the control socket, runs teetty
+/// and its hooks, and leaves a tombstone when it exits.
+/// Returns once teetty has created the session's stdin FIFO and stdout file.
+///
+/// The child keeps running Rust code after the fork, so this must only be called
+/// from a single-threaded process such as the via CLI; libraries use [`spawn`].
+pub fn spawn_forked(session: &str, command: &[String], options: &LaunchOptions) -> Result<()> {
+    let dir = prepare(session, command, options)?;
+    let mut cmd = teetty_command(&dir, command, options, true)?;
+    let hooks = hooks::load(&dir)?;
+
+    // Detach teetty from our stdio so it doesn't consume the shell's
+    // stdin or pollute stdout. teetty uses the FIFO and file instead.
+    cmd.stdin(Stdio::null());
+    cmd.stdout(Stdio::null());
+    cmd.stderr(Stdio::null());
+
+    // Fork before spawning teetty so the child process is teetty's
+    // actual parent and can wait on it for cleanup.
+    unsafe {
+        match libc::fork() {
+            -1 => anyhow::bail!("fork failed"),
+            0 => {
+                // Child: detach from terminal, serve the control socket,
+                // record output timing, run teetty (restarting it as asked),
+                // leave a tombstone
+                libc::setsid();
+                detach_stdio();
+                control::spawn_server(session);
+                index::spawn_recorder(session);
+                hooks::spawn_watcher(&dir, &hooks);
+                match supervise(&dir, command, options, &hooks, cmd) {
+                    Ok(status) => finish(&dir, &status, options),
+                    Err(_) => {
+                        let _ = std::fs::remove_dir_all(&dir);
+                        std::process::exit(1);
+                    }
+                }
+                std::process::exit(0);
+            }
+            _ => {}
+        }
+    }
+
+    // Parent: wait until teetty has created the stdin FIFO and stdout file
+    wait_for_files(&dir)
+}
+
+/// Poll until teetty has created the stdin FIFO and stdout file, then share
+/// them with the group in shared mode
+fn wait_for_files(dir: &Path) -> Result<()> {
+    let stdin_path = dir.join("stdin");
+    let stdout_path = dir.join("stdout");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        if stdin_path.exists() && stdout_path.exists() {
+            break;
+        }
+        if Instant::now() > deadline {
+            anyhow::bail!("teetty did not create stdin/stdout within 5s");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    session::share_with_group(&stdin_path)?;
+    session::share_with_group(&stdout_path)?;
+    Ok(())
+}
+
+/// Point stdin, stdout and stderr at /dev/null so the detached supervisor
+/// doesn't hold on to the caller's pipes (e.g. `$(via run --bg ...)` or an
+/// MCP client's stdio).
+fn detach_stdio() {
+    if let Ok(null) = std::fs::OpenOptions::new().read(true).write(true).open("/dev/null") {
+        use std::os::unix::io::AsRawFd;
+        for fd in 0..3 {
+            unsafe { libc::dup2(null.as_raw_fd(), fd); }
+        }
+    }
+}
+
+/// Run a claimed session in the foreground, serving the control socket from this
+/// process. Blocks until the command exits and returns its exit code.
+pub fn run(session: &str, command: &[String], options: &LaunchOptions) -> Result<i32> {
+    let dir = prepare(session, command, options)?;
+    let cmd = teetty_command(&dir, command, options, true)?;
+    let hooks = hooks::load(&dir)?;
+
+    // Set up cleanup handler for Ctrl-C
+    let dir_for_cleanup = dir.clone();
+    let options_for_cleanup = options.clone();
+    ctrlc::set_handler(move || {
+        finish(&dir_for_cleanup, &ExitStatus::from_raw(libc::SIGINT), &options_for_cleanup);
+        std::process::exit(130);
+    }).ok();
+
+    control::spawn_server(session);
+    index::spawn_recorder(session);
+    hooks::spawn_watcher(&dir, &hooks);
+
+    if session::shared_mode() {
+        let dir = dir.clone();
+        thread::spawn(move || wait_for_files(&dir));
+    }
+
+    // Run teetty in the foreground — blocks until the subprocess exits for good.
+    let status = supervise(&dir, command, options, &hooks, cmd)?;
+
+    finish(&dir, &status, options);
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Leave a tombstone in the session directory once the command has exited
+/// for good, or remove the directory if asked to (or if that fails)
+fn finish(dir: &Path, status: &ExitStatus, options: &LaunchOptions) {
+    if options.remove || tombstone::bury(dir, status).is_err() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
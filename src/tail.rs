@@ -1,276 +1,476 @@
 use anyhow::{Context, Result};
+use regex::Regex;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Write};
-use std::process::Command;
+use std::ops::Range;
 use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::error::Error;
+use crate::fifo;
+use crate::index;
 use crate::prompt;
+use crate::sentinel;
 use crate::session;
+use crate::shell;
+use crate::stanza;
+use crate::tombstone;
 
 /// Default timeout for --until polling (seconds)
 pub const DEFAULT_TIMEOUT: f64 = 30.0;
 
-#[derive(Debug)]
-enum TailMode {
-    Plain,
-    Since(String),
-    Delim(String),
+/// Check that a timeout (e.g. one a client sent) is a positive number of seconds
+/// a deadline can be set for
+pub fn check_timeout(timeout_secs: f64) -> Result<f64> {
+    deadline(timeout_secs).map(|_| timeout_secs)
 }
 
-struct TailOptions {
-    mode: TailMode,
-    lines: Option<usize>,
-    follow: bool,
-    until: Option<String>,
-    timeout_secs: Option<f64>,
+/// The instant `timeout_secs` from now, or an error for a timeout that is
+/// negative, zero, not a number or too large
+pub fn deadline(timeout_secs: f64) -> Result<Instant> {
+    Duration::try_from_secs_f64(timeout_secs).ok()
+        .filter(|timeout| !timeout.is_zero())
+        .and_then(|timeout| Instant::now().checked_add(timeout))
+        .ok_or_else(|| anyhow::anyhow!("invalid timeout: {} (use a positive number of seconds)", timeout_secs))
 }
 
-/// Parse tail arguments
-fn parse_tail_args(session: &str, args: &[String]) -> Result<TailOptions> {
-    let mut mode = TailMode::Plain;
-    let mut lines: Option<usize> = None;
-    let mut follow = false;
-    let mut until: Option<String> = None;
-    let mut timeout_secs: Option<f64> = None;
-    let mut i = 0;
-
-    while i < args.len() {
-        match args[i].as_str() {
-            "-f" => {
-                follow = true;
-                i += 1;
-            }
-            "-n" => {
-                if i + 1 >= args.len() {
-                    anyhow::bail!("usage: via {} tail -n <N> [--since PROMPT|--delim PROMPT|-f]", session);
-                }
-                lines = Some(args[i + 1].parse()
-                    .with_context(|| format!("invalid number: {}", args[i + 1]))?);
-                i += 2;
-            }
-            "--since" => {
-                if !matches!(mode, TailMode::Plain) {
-                    anyhow::bail!("only one of --since/--delim allowed");
-                }
-                let (val, consumed) = session::resolve_delim(session, args, i)?;
-                mode = TailMode::Since(val);
-                i += consumed;
-            }
-            "--delim" => {
-                if !matches!(mode, TailMode::Plain) {
-                    anyhow::bail!("only one of --since/--delim allowed");
-                }
-                let (val, consumed) = session::resolve_delim(session, args, i)?;
-                mode = TailMode::Delim(val);
-                i += consumed;
-            }
-            "--until" => {
-                let (val, consumed) = session::resolve_delim(session, args, i)?;
-                until = Some(val);
-                i += consumed;
-            }
-            "--timeout" => {
-                if i + 1 >= args.len() {
-                    anyhow::bail!("--timeout requires a number");
-                }
-                timeout_secs = Some(args[i + 1].parse()
-                    .with_context(|| format!("invalid timeout: {}", args[i + 1]))?);
-                i += 2;
-            }
-            _ => break,
-        }
-    }
-
-    // Validate combinations
-    if follow && !matches!(mode, TailMode::Plain) {
-        anyhow::bail!("-f cannot be combined with --since or --delim");
-    }
+/// The last `n` lines of a session's output
+pub fn tail_lines(session: &str, n: usize) -> Result<Vec<u8>> {
+    let stdout_path = session::stdout_path(session)?;
+    let mut file = File::open(&stdout_path)
+        .with_context(|| format!("no stdout at {} (is the session running?)", stdout_path.display()))?;
+    let start = last_lines_offset(&mut file, n)?;
+    Ok(read_from_offset(session, start)?.1)
+}
 
-    if follow && until.is_some() {
-        anyhow::bail!("-f cannot be combined with --until");
-    }
+/// Read everything from `offset` to the current end of the stdout file.
+/// Returns (start, bytes, end). An offset past the end of the file (e.g. one
+/// from an earlier session of the same name) is an error rather than a guess.
+pub fn read_from_offset(session: &str, offset: u64) -> Result<(u64, Vec<u8>, u64)> {
+    use std::io::{Read, Seek, SeekFrom};
 
-    if timeout_secs.is_some() && until.is_none() {
-        anyhow::bail!("--timeout requires --until");
-    }
+    let stdout_path = session::stdout_path(session)?;
+    let mut file = File::open(&stdout_path)
+        .with_context(|| format!("no stdout at {} (is the session running?)", stdout_path.display()))?;
 
-    if until.is_some() && matches!(mode, TailMode::Delim(_)) {
-        anyhow::bail!("--until cannot be combined with --delim");
+    let file_size = file.seek(SeekFrom::End(0))?;
+    if offset > file_size {
+        anyhow::bail!("offset {} is past the end of {}'s output ({} bytes)", offset, session, file_size);
     }
 
-    if matches!(mode, TailMode::Plain) && lines.is_none() && !follow && until.is_none() {
-        anyhow::bail!("usage: via <session> tail [-n N] (--since PROMPT | --delim PROMPT | --until PROMPT | -f)");
-    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity((file_size - offset) as usize);
+    file.take(file_size - offset).read_to_end(&mut data)?;
 
-    Ok(TailOptions { mode, lines, follow, until, timeout_secs })
+    let end = offset + data.len() as u64;
+    Ok((offset, data, end))
 }
 
-/// Tail a session's output
-pub fn tail_session(session: &str, args: &[String]) -> Result<()> {
-    let opts = parse_tail_args(session, args)?;
-    let stdout_path = session::stdout_path(session)?;
-
-    // --until mode: stream output until pattern matches
-    if let Some(ref pattern) = opts.until {
-        let timeout = opts.timeout_secs.unwrap_or(DEFAULT_TIMEOUT);
-
-        // If combined with --since, find the starting position from last prompt occurrence
-        let start_pos = if let TailMode::Since(ref since_prompt) = opts.mode {
-            find_since_position(session, since_prompt, opts.lines.unwrap_or(100))?
-        } else {
-            // Start from beginning so existing content is checked too
-            0
-        };
-
-        return follow_until(session, pattern, timeout, start_pos, &mut std::io::stdout());
-    }
+/// How to tell that a session has finished responding
+#[derive(Debug, Clone, PartialEq)]
+pub enum Completion {
+    /// A line containing this prompt has been written
+    Prompt(String),
+    /// The session has written nothing for this many milliseconds
+    Idle(u64),
+    /// A shell started with `--shell` has marked the command as finished.
+    /// Only the command's output (between its start and end marks) is kept.
+    Shell,
+    /// A line consisting of `marker` has been written by `command`, which is
+    /// sent after the input. The marker and the command's echo are hidden.
+    Sentinel { command: String, marker: String },
+}
 
-    if !stdout_path.exists() {
-        anyhow::bail!("no stdout at {}", stdout_path.display());
+impl std::fmt::Display for Completion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Completion::Prompt(prompt) => write!(f, "{}", prompt),
+            Completion::Idle(ms) => write!(f, "{}ms of silence", ms),
+            Completion::Shell => write!(f, "shell prompt"),
+            Completion::Sentinel { marker, .. } => write!(f, "{}", marker),
+        }
     }
+}
 
-    if opts.follow {
-        // Use external tail -f command
-        let mut cmd = Command::new("tail");
-        cmd.arg("-f");
-        if let Some(n) = opts.lines {
-            cmd.arg("-n").arg(n.to_string());
-        }
-        cmd.arg(&stdout_path);
+/// Check that the session is ready, write `input` and stream the response until
+/// it is complete. Returns the byte range of the response in the stdout file,
+/// and adds the raw range of each line written to `ranges` (see `follow_ranges`).
+pub fn send_and_follow(
+    session: &str,
+    input: &str,
+    completion: &Completion,
+    timeout_secs: f64,
+    writer: &mut dyn Write,
+    ranges: &mut Vec<Range<u64>>,
+) -> Result<(u64, u64)> {
+    use std::io::{Seek, SeekFrom};
 
-        let status = cmd.status()
-            .with_context(|| "failed to execute tail -f")?;
+    // 1. Check that the prompt is at the end of the output. Without a prompt
+    //    there is nothing to check.
+    match completion {
+        Completion::Prompt(prompt) => prompt::check_prompt_ready(session, prompt)?,
+        Completion::Shell => shell::check_ready(session)?,
+        Completion::Idle(_) | Completion::Sentinel { .. } => {}
+    }
 
-        if !status.success() {
-            anyhow::bail!("tail -f failed");
-        }
+    // 2. Record current file position before writing
+    let stdout_path = session::stdout_path(session)?;
+    let pos = File::open(&stdout_path)?.seek(SeekFrom::End(0))?;
 
-        return Ok(());
+    // 3. Write input, and the sentinel command that marks the end of its output
+    fifo::write_input(session, input)?;
+    if let Completion::Sentinel { command, .. } = completion {
+        fifo::write_sentinel(session, input, command)?;
     }
 
-    match opts.mode {
-        TailMode::Plain => {
-            // Simple tail -n N
-            let n = opts.lines.unwrap(); // Already validated
-            let output = Command::new("tail")
-                .arg("-n")
-                .arg(n.to_string())
-                .arg(&stdout_path)
-                .output()
-                .with_context(|| "failed to execute tail")?;
-
-            Write::write_all(&mut std::io::stdout(), &output.stdout)?;
-        }
-        TailMode::Since(prompt) => {
-            tail_since(session, &prompt, opts.lines.unwrap_or(100))?;
-        }
-        TailMode::Delim(prompt) => {
-            tail_delim_internal(session, &prompt, opts.lines.unwrap_or(100))?;
-        }
-    }
+    // 4. Stream output until the response is complete
+    let end = follow_ranges(session, completion, timeout_secs, pos, writer, ranges)?;
+    Ok((pos, end))
+}
 
-    Ok(())
+/// Wait until the session is ready: a sentinel session is sent its sentinel
+/// command and followed from the current end of its output, anything else is
+/// followed from `start_pos` until `completion` is met. Adds the raw range of
+/// each line written to `ranges`.
+pub fn wait(
+    session: &str,
+    completion: &Completion,
+    timeout_secs: f64,
+    start_pos: u64,
+    writer: &mut dyn Write,
+    ranges: &mut Vec<Range<u64>>,
+) -> Result<u64> {
+    if let Completion::Sentinel { command, .. } = completion {
+        let pos = std::fs::metadata(session::stdout_path(session)?).map(|m| m.len()).unwrap_or(0);
+        fifo::write_sentinel(session, "", command)?;
+        return follow_ranges(session, completion, timeout_secs, pos, writer, ranges);
+    }
+    follow_ranges(session, completion, timeout_secs, start_pos, writer, ranges)
 }
 
 /// Stream output from `start_pos` until `pattern` appears in a line, writing to `writer`.
 /// Waits for the stdout file to exist if it doesn't yet.
+/// Returns the byte offset just past the line that matched.
 pub fn follow_until(
     session: &str,
     pattern: &str,
     timeout_secs: f64,
     start_pos: u64,
     writer: &mut dyn Write,
-) -> Result<()> {
+) -> Result<u64> {
+    follow(session, &Completion::Prompt(pattern.to_string()), timeout_secs, start_pos, writer)
+}
+
+/// Stream output from `start_pos` until `completion` is met, writing each line
+/// to `writer`. Waits for the stdout file to exist if it doesn't yet.
+/// Returns the byte offset just past the output that was written.
+pub fn follow(
+    session: &str,
+    completion: &Completion,
+    timeout_secs: f64,
+    start_pos: u64,
+    writer: &mut dyn Write,
+) -> Result<u64> {
+    follow_ranges(session, completion, timeout_secs, start_pos, writer, &mut Vec::new())
+}
+
+/// Like `follow`, also adding to `ranges` the byte range in the stdout file of
+/// the raw line behind each line written. Lines that aren't written (echoes,
+/// shell integration marks, sentinels) leave gaps between the ranges.
+pub fn follow_ranges(
+    session: &str,
+    completion: &Completion,
+    timeout_secs: f64,
+    start_pos: u64,
+    writer: &mut dyn Write,
+    ranges: &mut Vec<Range<u64>>,
+) -> Result<u64> {
+    use std::io::{Read, Seek, SeekFrom};
+
     let stdout_path = session::stdout_path(session)?;
-    let poll_interval = Duration::from_millis(100);
-    let deadline = Instant::now() + Duration::from_secs_f64(timeout_secs);
+    let tombstone_path = session::session_path(session)?.join("tombstone");
+    let poll_interval = Duration::from_millis(match completion {
+        Completion::Idle(ms) => (*ms).clamp(1, 100),
+        _ => 100,
+    });
+    let deadline = deadline(timeout_secs)?;
     let mut pos = start_pos;
+    let mut last_size = None;
+    let mut last_change = Instant::now();
+    // Whether a shell command's output has started (its C mark was seen)
+    let mut started = false;
 
     loop {
         if Instant::now() > deadline {
-            anyhow::bail!("timeout waiting for '{}' (after {}s)", pattern, timeout_secs);
+            return Err(Error::Timeout { pattern: completion.to_string(), secs: timeout_secs }.into());
         }
 
-        // Wait for file to exist
-        if !stdout_path.exists() {
-            thread::sleep(poll_interval);
-            continue;
-        }
+        // Checked before reading, so output written before the end is still seen
+        let ended = tombstone_path.exists();
 
+        // Wait for file to exist, unless it's gone because the session ended
         let mut file = match File::open(&stdout_path) {
             Ok(f) => f,
             Err(_) => {
+                tombstone::check_live(session)?;
                 thread::sleep(poll_interval);
                 continue;
             }
         };
 
-        use std::io::{Seek, SeekFrom};
         let file_size = file.seek(SeekFrom::End(0))?;
+        if last_size != Some(file_size) {
+            last_size = Some(file_size);
+            last_change = Instant::now();
+        }
+        let idle = match completion {
+            Completion::Idle(ms) => last_change.elapsed() >= Duration::from_millis(*ms),
+            _ => false,
+        };
+
+        if file_size > pos {
+            file.seek(SeekFrom::Start(pos))?;
+            let mut data = Vec::new();
+            file.take(file_size - pos).read_to_end(&mut data)
+                .with_context(|| "failed to read from stdout")?;
+
+            // Complete lines, then a trailing partial line (such as a prompt)
+            for line in data.split_inclusive(|&b| b == b'\n') {
+                let partial = !line.ends_with(b"\n");
+                let matched = match completion {
+                    Completion::Prompt(pattern) => prompt::strip_ansi(line).contains(pattern.as_str()),
+                    Completion::Shell => shell::find_mark(line, b'D').is_some(),
+                    Completion::Idle(_) => false,
+                    Completion::Sentinel { marker, .. } => {
+                        sentinel::is_marker(&prompt::strip_ansi(line), marker)
+                    }
+                };
+
+                // A partial line is only consumed once it completes the
+                // response; otherwise its rest is still being written
+                if partial && !matched && !idle {
+                    break;
+                }
+
+                let mut text = trim_line_ending(line);
+                if let Completion::Shell = completion {
+                    // Skip the echoed command line, then keep what lies between the marks
+                    if !started {
+                        if let Some((_, c_end)) = shell::find_mark(text, b'C') {
+                            started = true;
+                            text = &text[c_end..];
+                        }
+                    }
+                    if let Some((d_start, _)) = shell::find_mark(text, b'D') {
+                        text = &text[..d_start];
+                    }
+                }
 
-        if file_size <= pos {
-            thread::sleep(poll_interval);
-            continue;
+                let visible = match completion {
+                    Completion::Shell => started && !(matched && text.is_empty()),
+                    // The sentinel command is echoed at the end of a line (after
+                    // a prompt): by the terminal if typed ahead, then by readline
+                    Completion::Sentinel { command, .. } => {
+                        !matched && !prompt::strip_ansi(text).trim_end().ends_with(command.as_str())
+                    }
+                    _ => true,
+                };
+                if visible {
+                    writeln!(writer, "{}", String::from_utf8_lossy(text))?;
+                    ranges.push(pos..pos + line.len() as u64);
+                }
+                pos += line.len() as u64;
+
+                if matched {
+                    return Ok(pos);
+                }
+            }
         }
 
-        // Read new content
-        file.seek(SeekFrom::Start(pos))?;
-        let reader = BufReader::new(&file);
-        for line_result in reader.lines() {
-            let line = line_result.with_context(|| "failed to read line from stdout")?;
-            writeln!(writer, "{}", line)?;
+        if idle {
+            return Ok(pos);
+        }
+        if ended {
+            tombstone::check_live(session)?;
+        }
+
+        thread::sleep(poll_interval);
+    }
+}
 
-            // Check if this line contains the pattern (strip ANSI for matching)
-            if prompt::strip_ansi(line.as_bytes()).contains(pattern) {
+/// How `tail` shows the lines it prints
+#[derive(Debug, Default)]
+pub struct LineFilter {
+    /// Prefix each line with the local time it was written
+    pub timestamps: bool,
+    /// Strip ANSI escape sequences
+    pub plain: bool,
+    /// Only show lines matching this (escape sequences removed)
+    pub grep: Option<Regex>,
+}
+
+impl LineFilter {
+    /// Whether output can be passed through byte for byte
+    fn is_raw(&self) -> bool {
+        !self.timestamps && !self.plain && self.grep.is_none()
+    }
+
+    /// Write one line of output written at `time_ms`, if it passes the filter
+    pub fn write_line(&self, writer: &mut dyn Write, line: &[u8], time_ms: u64) -> Result<()> {
+        let line = trim_line_ending(line);
+        if let Some(ref grep) = self.grep {
+            if !grep.is_match(&prompt::strip_ansi(line)) {
                 return Ok(());
             }
         }
+        if self.timestamps {
+            write!(writer, "[{}] ", index::format_time(time_ms))?;
+        }
+        if self.plain {
+            writer.write_all(prompt::strip_ansi(line).as_bytes())?;
+        } else {
+            writer.write_all(line)?;
+        }
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+/// Follow output like `tail -f`, starting with the last `lines` lines (timed
+/// from the session's index). Returns once a line of new output contains
+/// `until`, or once the session ends.
+pub fn follow_lines(
+    session: &str,
+    lines: usize,
+    filter: &LineFilter,
+    until: Option<&str>,
+    timeout_secs: Option<f64>,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let stdout_path = session::stdout_path(session)?;
+    let tombstone_path = session::session_path(session)?.join("tombstone");
+    let mut file = File::open(&stdout_path)
+        .with_context(|| format!("no stdout at {} (is the session running?)", stdout_path.display()))?;
+    let checkpoints = index::load(session)?;
+    let backlog_end = file.seek(SeekFrom::End(0))?;
+    let mut pos = last_lines_offset(&mut file, lines)?;
+    let deadline = timeout_secs.map(deadline).transpose()?;
+    let raw = filter.is_raw() && until.is_none();
+
+    loop {
+        // Checked before reading, so output written before the end is still seen
+        let gone = !stdout_path.exists() || tombstone_path.exists();
+        let file_size = file.seek(SeekFrom::End(0))?;
+        // A new session of the same name truncated the file
+        if file_size < pos {
+            pos = 0;
+        }
+        let before = pos;
+
+        if file_size > pos {
+            file.seek(SeekFrom::Start(pos))?;
+            let mut data = Vec::new();
+            (&mut file).take(file_size - pos).read_to_end(&mut data)
+                .with_context(|| "failed to read from stdout")?;
+
+            if raw {
+                writer.write_all(&data)?;
+                pos = file_size;
+            } else {
+                for line in data.split_inclusive(|&b| b == b'\n') {
+                    // Only new output can end the follow: the backlog usually
+                    // ends with the very prompt being waited for
+                    let matched = pos >= backlog_end
+                        && until.is_some_and(|p| prompt::strip_ansi(line).contains(p));
+
+                    // Wait for the rest of a partial line, unless nothing more is coming
+                    if !line.ends_with(b"\n") && !matched && !gone {
+                        break;
+                    }
+
+                    let time = if pos < backlog_end { index::time_of(&checkpoints, pos) } else { index::now_ms() };
+                    filter.write_line(writer, line, time)?;
+                    pos += line.len() as u64;
+
+                    if matched {
+                        writer.flush()?;
+                        return Ok(());
+                    }
+                }
+            }
+            writer.flush()?;
+        }
 
-        // Update position to current end
-        pos = file_size;
-        thread::sleep(poll_interval);
+        if gone {
+            return Ok(());
+        }
+        if pos == before {
+            if let (Some(deadline), Some(secs)) = (deadline, timeout_secs) {
+                if Instant::now() > deadline {
+                    return Err(Error::Timeout { pattern: until.unwrap_or_default().to_string(), secs }.into());
+                }
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Offset where the last `n` lines of a file start
+pub fn last_lines_offset(file: &mut File, n: usize) -> Result<u64> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let file_size = file.seek(SeekFrom::End(0))?;
+    let mut end = file_size;
+    let mut newlines = 0;
+    let mut chunk = vec![0u8; 8192];
+
+    while end > 0 {
+        let start = end.saturating_sub(chunk.len() as u64);
+        let len = (end - start) as usize;
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk[..len])?;
+
+        for i in (0..len).rev() {
+            // A newline at the very end terminates the last line rather than starting one
+            if chunk[i] == b'\n' && start + i as u64 + 1 != file_size {
+                newlines += 1;
+                if newlines == n {
+                    return Ok(start + i as u64 + 1);
+                }
+            }
+        }
+        end = start;
     }
+
+    Ok(0)
+}
+
+/// Strip a trailing "\n" or "\r\n", like `BufRead::lines` does
+fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
 }
 
 /// Find the byte position of the last occurrence of a prompt in the stdout file.
 /// Used by `--since` combined with `--until` to determine where to start streaming.
-fn find_since_position(session: &str, prompt: &str, window: usize) -> Result<u64> {
+pub fn find_since_position(session: &str, prompt: &str) -> Result<u64> {
     let stdout_path = session::stdout_path(session)?;
 
     if !stdout_path.exists() {
         return Ok(0);
     }
 
-    let file = File::open(&stdout_path)
-        .with_context(|| format!("failed to open {}", stdout_path.display()))?;
-
-    let lines = read_back_until(&file, window, |lines| {
-        lines.iter().any(|line| prompt::strip_ansi(line.as_bytes()).contains(prompt))
-    })?;
-
-    // Find byte offset: we need to figure out where in the file the matching line starts.
-    // Read backwards from the end to find the prompt line's byte position.
-    use std::io::{Seek, SeekFrom};
-    let mut file = file.try_clone()?;
-    let file_size = file.seek(SeekFrom::End(0))?;
-
-    // Find the last occurrence of prompt in lines
-    if let Some(idx) = lines.iter().rposition(|line| prompt::strip_ansi(line.as_bytes()).contains(prompt)) {
-        // Estimate: sum bytes of lines after the match to get offset from end
-        let bytes_after: usize = lines[idx..].iter()
-            .map(|l| l.len() + 1) // +1 for newline
-            .sum();
-        let start = if bytes_after as u64 >= file_size { 0 } else { file_size - bytes_after as u64 };
-        Ok(start)
-    } else {
-        // No prompt found, start from current end
-        Ok(file_size)
-    }
+    // The last stanza starts at the last prompt line; without a prompt, start
+    // from the current end
+    let data = stanza::read_output(session)?;
+    let (_, stanzas) = stanza::split(&data, prompt);
+    Ok(stanzas.last().map(|s| s.start).unwrap_or(data.len() as u64))
 }
 
-/// Tail since the last occurrence of a prompt (includes prompt)
-fn tail_since(session: &str, prompt: &str, window: usize) -> Result<()> {
+/// Lines since the last occurrence of a prompt (includes prompt)
+pub fn tail_since(session: &str, prompt: &str, window: usize) -> Result<Vec<String>> {
     let stdout_path = session::stdout_path(session)?;
 
     let file = File::open(&stdout_path)
@@ -281,18 +481,14 @@ fn tail_since(session: &str, prompt: &str, window: usize) -> Result<()> {
     })?;
 
     // Find the last occurrence of prompt (strip ANSI before matching)
-    if let Some(idx) = lines.iter().rposition(|line| prompt::strip_ansi(line.as_bytes()).contains(prompt)) {
-        // Print from that line onwards
-        for line in &lines[idx..] {
-            println!("{}", line);
-        }
+    match lines.iter().rposition(|line| prompt::strip_ansi(line.as_bytes()).contains(prompt)) {
+        Some(idx) => Ok(lines[idx..].to_vec()),
+        None => Ok(Vec::new()),
     }
-
-    Ok(())
 }
 
-/// Tail the last stanza delimited by prompt (from second-to-last prompt, excluding last prompt)
-fn tail_delim_internal(session: &str, prompt: &str, window: usize) -> Result<()> {
+/// The last stanza delimited by prompt (from second-to-last prompt, excluding last prompt)
+pub fn tail_delim_internal(session: &str, prompt: &str, window: usize) -> Result<Vec<String>> {
     let stdout_path = session::stdout_path(session)?;
 
     let file = File::open(&stdout_path)
@@ -311,21 +507,27 @@ fn tail_delim_internal(session: &str, prompt: &str, window: usize) -> Result<()>
         .collect();
 
     if indices.len() >= 2 {
-        // Print from second-to-last prompt up to (but not including) the last prompt
+        // From second-to-last prompt up to (but not including) the last prompt
         let start = indices[indices.len() - 2];
         let end = indices[indices.len() - 1];
-        for line in &lines[start..end] {
-            println!("{}", line);
-        }
+        Ok(lines[start..end].to_vec())
     } else if indices.len() == 1 {
-        // Only one prompt found, print from there onwards
-        let start = indices[0];
-        for line in &lines[start..] {
-            println!("{}", line);
-        }
+        // Only one prompt found, take everything from there onwards
+        Ok(lines[indices[0]..].to_vec())
+    } else {
+        Ok(Vec::new())
     }
+}
 
-    Ok(())
+/// The output of the last stanza: the stanza without its prompt line (which
+/// holds the echoed input), with ANSI escapes and trailing whitespace removed
+pub fn last_stanza_output(session: &str, prompt: &str) -> Result<String> {
+    let lines = tail_delim_internal(session, prompt, 100)?;
+    let output: Vec<String> = lines.iter()
+        .skip(1)
+        .map(|line| prompt::strip_ansi(line.as_bytes()).replace('\r', ""))
+        .collect();
+    Ok(output.join("\n").trim_end().to_string())
 }
 
 /// Read backwards from the end of a file, doubling the byte window until
@@ -348,11 +550,7 @@ fn read_back_until(
     let mut read_bytes = (min_lines as u64) * 80;
 
     loop {
-        let start_pos = if read_bytes >= file_size {
-            0
-        } else {
-            file_size - read_bytes
-        };
+        let start_pos = file_size.saturating_sub(read_bytes);
 
         file.seek(SeekFrom::Start(start_pos))?;
 
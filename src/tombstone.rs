@@ -0,0 +1,147 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+
+use crate::error::Error;
+use crate::index;
+use crate::prompt;
+use crate::session;
+
+// When a session's command exits for good, its supervisor replaces the live
+// files (input FIFO, control socket, pid) with a `tombstone`: a JSON record of
+// how the command ended and the last lines it printed, often the reason it
+// crashed. The metadata and transcript (output, index, inputs) stay, so the
+// session is still listed, its output can still be read and `via <session>
+// status` can explain what happened, until `via gc` removes it or a new `run`
+// reclaims the name.
+
+/// Lines of final output kept in a tombstone
+const FINAL_LINES: usize = 20;
+
+/// Bytes read from the end of the output to find the final lines
+const FINAL_BYTES: u64 = 16384;
+
+/// Files that only mean something while the session runs
+const LIVE_FILES: &[&str] = &["stdin", "control", "pid", "stopped"];
+const LIVE_DIRS: &[&str] = &["integration"];
+
+/// How a session ended
+#[derive(Debug, Clone)]
+pub struct Tombstone {
+    /// Exit code, if the command exited on its own
+    pub exit_code: Option<i32>,
+    /// Signal that killed the command, if any
+    pub signal: Option<i32>,
+    /// When it ended, in ms since the epoch
+    pub ended_ms: u64,
+    /// The last lines of output, escape sequences removed
+    pub output: String,
+}
+
+impl Tombstone {
+    /// Short form for the listing, e.g. `exited 1` or `signal 9`
+    pub fn state(&self) -> String {
+        match (self.exit_code, self.signal) {
+            (_, Some(signal)) => format!("signal {}", signal),
+            (Some(code), None) => format!("exited {}", code),
+            (None, None) => "ended".to_string(),
+        }
+    }
+
+    /// Long form, e.g. `exited with status 1` or `killed by signal 9`
+    pub fn describe(&self) -> String {
+        match (self.exit_code, self.signal) {
+            (_, Some(signal)) => format!("killed by signal {}", signal),
+            (Some(code), None) => format!("exited with status {}", code),
+            (None, None) => "ended".to_string(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "exit_code": self.exit_code,
+            "signal": self.signal,
+            "ended_ms": self.ended_ms,
+            "output": self.output,
+        })
+    }
+
+    fn from_json(value: &Value) -> Tombstone {
+        Tombstone {
+            exit_code: value["exit_code"].as_i64().map(|c| c as i32),
+            signal: value["signal"].as_i64().map(|s| s as i32),
+            ended_ms: value["ended_ms"].as_u64().unwrap_or(0),
+            output: value["output"].as_str().unwrap_or("").to_string(),
+        }
+    }
+}
+
+/// Record how the command in session directory `dir` ended, then remove the
+/// files that only mean something while it runs. The tombstone is in place
+/// before the input FIFO goes, so a client that can't find it can tell why.
+pub fn bury(dir: &Path, status: &ExitStatus) -> Result<()> {
+    let tombstone = Tombstone {
+        exit_code: status.code(),
+        signal: status.signal(),
+        ended_ms: index::now_ms(),
+        output: final_output(&dir.join("stdout")).unwrap_or_default(),
+    };
+
+    // Write then rename, so readers never see a partial tombstone
+    let partial = dir.join("tombstone.tmp");
+    fs::write(&partial, format!("{}\n", tombstone.to_json()))
+        .with_context(|| "failed to write tombstone")?;
+    fs::rename(&partial, dir.join("tombstone"))
+        .with_context(|| "failed to write tombstone")?;
+
+    for name in LIVE_FILES {
+        let _ = fs::remove_file(dir.join(name));
+    }
+    for name in LIVE_DIRS {
+        let _ = fs::remove_dir_all(dir.join(name));
+    }
+    Ok(())
+}
+
+/// The last lines of an output file, escape sequences removed
+fn final_output(stdout_path: &Path) -> Result<String> {
+    let mut file = File::open(stdout_path)?;
+    let size = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(size.saturating_sub(FINAL_BYTES)))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+
+    let mut lines = Vec::new();
+    for line in data.split(|&b| b == b'\n') {
+        lines.push(prompt::process_terminal_output(line)?);
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    let start = lines.len().saturating_sub(FINAL_LINES);
+    Ok(lines[start..].join("\n"))
+}
+
+/// The tombstone in a session directory, if the session has ended
+pub fn read_dir(dir: &Path) -> Option<Tombstone> {
+    let text = fs::read_to_string(dir.join("tombstone")).ok()?;
+    let value: Value = serde_json::from_str(&text).ok()?;
+    Some(Tombstone::from_json(&value))
+}
+
+/// A session's tombstone, if it has ended
+pub fn read(session: &str) -> Result<Option<Tombstone>> {
+    Ok(read_dir(&session::session_path(session)?))
+}
+
+/// Fail with `Error::Ended` if the session has ended
+pub fn check_live(session: &str) -> Result<()> {
+    match read(session)? {
+        Some(tombstone) => Err(Error::Ended { name: session.to_string(), status: tombstone.describe() }.into()),
+        None => Ok(()),
+    }
+}
@@ -6,6 +6,9 @@
 # Commands:
 #   :quit        — exit
 #   :long N      — print N lines of output (default 500)
+#   :color TEXT  — print TEXT in red
+#   :slow N      — print a line, pause N seconds, print another
+#   LINE:        — start a block: read lines up to a blank one, like python
 #   anything     — echo "=> <input>"
 PROMPT="${1:-mock>} "
 printf '%s' "$PROMPT"
@@ -21,6 +24,21 @@ while true; do
           printf 'line %03d: abcdefghijklmnopqrstuvwxyz 0123456789 the quick brown fox jumps over the lazy dog\n' "$i"
         done
         ;;
+      :slow*)
+        echo "slow start"
+        sleep "${line#:slow }"
+        echo "slow end"
+        ;;
+      :color*) printf '\033[31m%s\033[0m\n' "${line#:color }" ;;
+      *:)
+        n=1
+        printf '... '
+        while IFS= read -r body && [ -n "$body" ]; do
+          n=$((n + 1))
+          printf '... '
+        done
+        echo "=> block of $n lines"
+        ;;
       *) echo "=> $line" ;;
     esac
     printf '%s' "$PROMPT"
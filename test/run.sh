@@ -6,14 +6,23 @@ set -euo pipefail
 # Usage: test/run.sh [path-to-via]
 
 VIA="${1:-via}"
+# Make a relative path absolute so tests can run via from other directories
+if [[ "$VIA" == */* ]]; then
+  VIA="$(cd "$(dirname "$VIA")" && pwd)/$(basename "$VIA")"
+fi
 DIR="$(cd "$(dirname "$0")" && pwd)"
 MOCK="$DIR/mock-repl.sh"
 export REPLS_DIR="${REPLS_DIR:-$(mktemp -d)}"
+# Fixtures that aren't sessions live outside the base dir, so they don't show
+# up in the listing
+SCRATCH="$(mktemp -d)"
+# Keep the user's hooks out of the tests
+export XDG_CONFIG_HOME="$SCRATCH/config"
 PASS=0
 FAIL=0
 ERRORS=()
 
-cleanup() { rm -rf "$REPLS_DIR"; }
+cleanup() { rm -rf "$REPLS_DIR" "$SCRATCH"; }
 trap cleanup EXIT
 
 # ── helpers ──────────────────────────────────────────────────────────
@@ -110,6 +119,33 @@ assert_contains "shorthand sends input" "=> hello" "$VIA" test-01 hello
 assert_contains "shorthand with --timeout" "=> world" "$VIA" test-01 --timeout 10 world
 assert_contains "piped stdin" "=> piped" sh -c "echo piped | $VIA test-01"
 
+# ── control socket ───────────────────────────────────────────────────
+echo "# control socket"
+
+if [ -S "$REPLS_DIR/test-01/control" ]; then
+  pass "control socket created"
+else
+  fail "control socket created" "no socket at $REPLS_DIR/test-01/control"
+fi
+
+# Concurrent clients are serialized by the supervisor instead of racing
+"$VIA" test-01 concurrent-a > "$REPLS_DIR/concurrent-a.out" 2>&1 &
+"$VIA" test-01 concurrent-b > "$REPLS_DIR/concurrent-b.out" 2>&1 || true
+wait
+assert_contains "concurrent shorthand (first)" "=> concurrent-a" cat "$REPLS_DIR/concurrent-a.out"
+assert_contains "concurrent shorthand (second)" "=> concurrent-b" cat "$REPLS_DIR/concurrent-b.out"
+
+# Output shows as it arrives, not only once the prompt is back
+"$VIA" test-01 ':slow 2' > "$SCRATCH/stream.out" 2>&1 &
+sleep 1
+if grep -q "slow start" "$SCRATCH/stream.out" && ! grep -q "slow end" "$SCRATCH/stream.out"; then
+  pass "shorthand streams output"
+else
+  fail "shorthand streams output" "after 1s: $(cat "$SCRATCH/stream.out")"
+fi
+wait
+assert_contains "streamed output is complete" "slow end" cat "$SCRATCH/stream.out"
+
 # ── tail bare flags ──────────────────────────────────────────────────
 echo "# tail bare flags"
 
@@ -125,6 +161,500 @@ assert_contains "tail --since (explicit)" "mock>" "$VIA" test-01 tail --since 'm
 assert_ok "tail --until (explicit)" "$VIA" test-01 tail --until 'mock>' --timeout 5
 assert_ok "tail --delim (explicit)" "$VIA" test-01 tail --delim 'mock>'
 
+# ── offsets & cursors ────────────────────────────────────────────────
+echo "# offsets & cursors"
+
+assert_stderr_contains "tail --from-offset reports offset" "[via] offset:" "$VIA" test-01 tail --from-offset 0
+assert_contains "tail --from-offset --json" '"end":' "$VIA" test-01 tail --from-offset 0 --json
+assert_stderr_contains "tail --from-offset past the end" "past the end" "$VIA" test-01 tail --from-offset 999999999
+"$VIA" test-01 tail --cursor agent1 >/dev/null 2>&1
+"$VIA" test-01 cursor-check >/dev/null 2>&1
+assert_contains "tail --cursor returns new output" "=> cursor-check" "$VIA" test-01 tail --cursor agent1
+# A cursor only moves once its output was written
+"$VIA" test-01 cursor-retry >/dev/null 2>&1
+"$VIA" test-01 tail --cursor agent1 >/dev/full 2>/dev/null || true
+assert_contains "tail --cursor retries after a failed write" "=> cursor-retry" "$VIA" test-01 tail --cursor agent1
+cursor_output=$("$VIA" test-01 tail --cursor agent1 2>/dev/null)
+if [[ "$cursor_output" != *"cursor-check"* ]]; then
+  pass "tail --cursor advances"
+else
+  fail "tail --cursor advances" "old output repeated: $cursor_output"
+fi
+
+# ── idle completion ──────────────────────────────────────────────────
+echo "# idle completion"
+
+assert_contains "shorthand --idle" "=> idle-check" "$VIA" test-01 --idle 300 idle-check
+"$VIA" test-01 write idle-tail >/dev/null 2>&1
+assert_contains "tail --idle" "=> idle-tail" "$VIA" test-01 tail --idle 300
+"$VIA" quiet-01 run --idle 300 --bg -- bash "$MOCK" 'quiet>' 2>/dev/null
+assert_stderr_contains "wait uses stored idle" "silent for 300ms" "$VIA" quiet-01 wait --timeout 10
+assert_contains "shorthand uses stored idle" "=> no-prompt" "$VIA" quiet-01 no-prompt
+stop_session quiet-01
+
+# ── delim detection ──────────────────────────────────────────────────
+echo "# delim detection"
+
+assert_stderr_contains "run --detect-delim" "detected delim: 'detect>'" \
+  "$VIA" detect-01 run --detect-delim --bg -- bash "$MOCK" 'detect>'
+assert_contains "detected delim is stored" "=> detected" "$VIA" detect-01 detected
+stop_session detect-01
+"$VIA" detect-02 run --bg -- bash -c 'n=1; while printf "In [%d]: " $n; read -r l; do n=$((n+1)); done' 2>/dev/null
+assert_contains "detect-delim numbered prompt" "]:" "$VIA" detect-02 detect-delim --store
+assert_ok "detect-delim --store enables shorthand" "$VIA" detect-02 probe
+kill "$(cat "$REPLS_DIR/detect-02/pid")" 2>/dev/null || true
+"$VIA" detect-03 run --bg -- bash -c 'n=1; while printf "%d%%" $n; read -r l; do n=$((n+1)); done' 2>/dev/null
+assert_stderr_contains "detect-delim rejects a one-character prompt" "too short" "$VIA" detect-03 detect-delim
+assert_stderr_contains "detect-delim rejects a negative timeout" "invalid timeout" "$VIA" detect-03 detect-delim --timeout -1
+kill "$(cat "$REPLS_DIR/detect-03/pid")" 2>/dev/null || true
+sleep 0.5
+
+# ── shell integration ────────────────────────────────────────────────
+echo "# shell integration"
+
+"$VIA" shell-01 run --shell --bg -- bash 2>/dev/null
+"$VIA" shell-01 wait --timeout 10 >/dev/null 2>&1
+shell_output=$("$VIA" shell-01 'echo from-shell' 2>&1)
+if [[ "$shell_output" == "from-shell" ]]; then
+  pass "--shell output is delimited by marks"
+else
+  fail "--shell output is delimited by marks" "got: $shell_output"
+fi
+if "$VIA" shell-01 'exit_with() { return $1; }; exit_with 3' >/dev/null 2>&1; then
+  fail "--shell exit status" "expected exit 3, got success"
+else
+  status=$?
+  if [[ $status -eq 3 ]]; then pass "--shell exit status"; else fail "--shell exit status" "expected 3, got $status"; fi
+fi
+assert_ok "--shell success status" "$VIA" shell-01 true
+# The omitted range covers exactly the omitted lines, though the echo and marks were dropped
+shell_omitted=$("$VIA" shell-01 --max-lines 4 'seq 1 20' 2>&1 | grep -o 'output [0-9]* [0-9]*')
+shell_range=$("$VIA" shell-01 $shell_omitted 2>&1 | tr -d '\r')
+if [[ $(head -1 <<< "$shell_range") == 3 && $(tail -1 <<< "$shell_range") == 18 ]]; then
+  pass "--shell budget names the omitted bytes"
+else
+  fail "--shell budget names the omitted bytes" "got: $shell_range"
+fi
+kill "$(cat "$REPLS_DIR/shell-01/pid")" 2>/dev/null || true
+assert_fails "--shell rejects other programs" "$VIA" shell-02 run --shell --bg -- python3
+sleep 0.5
+
+# ── sentinel ─────────────────────────────────────────────────────────
+echo "# sentinel"
+
+"$VIA" sent-01 run --sentinel --bg -- bash --norc 2>/dev/null
+"$VIA" sent-01 wait --timeout 10 >/dev/null 2>&1
+assert_ok "--sentinel stores the preset" grep -q 'echo {marker}' "$REPLS_DIR/sent-01/sentinel"
+sent_output=$("$VIA" sent-01 "echo 'fake \$ prompt'; sleep 0.3; echo after" 2>&1)
+if [[ "$sent_output" == *"after"* && "$sent_output" != *"VIA-"* ]]; then
+  pass "--sentinel ends at the marker and hides it"
+else
+  fail "--sentinel ends at the marker and hides it" "got: $sent_output"
+fi
+sent_record=$("$VIA" sent-01 record 2>&1)
+if [[ "$sent_record" == *"echo after"* && "$sent_record" != *"VIA-"[0-9a-f]* ]]; then
+  pass "recordings leave out the sentinel"
+else
+  fail "recordings leave out the sentinel" "got: $sent_record"
+fi
+kill "$(cat "$REPLS_DIR/sent-01/pid")" 2>/dev/null || true
+# A multi-line block is ended before the sentinel, or python would read it as part of the block
+if command -v python3 >/dev/null; then
+  "$VIA" sent-py run --sentinel --bg -- python3 -q 2>/dev/null
+  "$VIA" sent-py wait --timeout 10 >/dev/null 2>&1
+  assert_contains "--sentinel after a multi-line block" "row 1" \
+    sh -c "printf 'for i in range(2):\\n    print(\"row\", i)\\n' | \"$VIA\" sent-py --timeout 10"
+  kill "$(cat "$REPLS_DIR/sent-py/pid")" 2>/dev/null || true
+fi
+assert_fails "--sentinel needs a preset or template" "$VIA" sent-02 run --sentinel --bg -- cat
+assert_fails "--sentinel template needs {marker}" "$VIA" sent-03 run --sentinel 'echo hi' --bg -- cat
+sleep 0.5
+
+# ── timestamps ───────────────────────────────────────────────────────
+echo "# timestamps"
+
+"$VIA" test-01 old-output >/dev/null 2>&1
+sleep 2
+"$VIA" test-01 recent-output >/dev/null 2>&1
+sleep 0.3
+assert_ok "index file recorded" test -s "$REPLS_DIR/test-01/index"
+recent=$("$VIA" test-01 tail --since-time 1s 2>/dev/null)
+if [[ "$recent" == *"recent-output"* && "$recent" != *"old-output"* ]]; then
+  pass "tail --since-time"
+else
+  fail "tail --since-time" "expected only recent output, got: $recent"
+fi
+stamped=$(timeout 1 "$VIA" test-01 tail -f --timestamps -n 2 2>/dev/null) || true
+if [[ "$stamped" =~ ^\[[0-9]{2}:[0-9]{2}:[0-9]{2}\]\ .*recent-output ]]; then
+  pass "tail -f --timestamps"
+else
+  fail "tail -f --timestamps" "unexpected output: $stamped"
+fi
+assert_contains "log merges sessions" "test-01  => recent-output" "$VIA" log --since 1m 'test-*'
+assert_fails "log rejects a negative duration" "$VIA" log --since -5s
+assert_fails "log rejects an overlong duration" "$VIA" log --since 99999999999999999999999d
+assert_fails "tail --timestamps without -f" "$VIA" test-01 tail -n 2 --timestamps
+
+# ── native follow ────────────────────────────────────────────────────
+echo "# native follow"
+
+(sleep 0.5; "$VIA" test-01 ':color follow-red' >/dev/null 2>&1) &
+followed=$(timeout 5 "$VIA" test-01 tail -f -n 1 --until 'mock>' --plain 2>/dev/null)
+follow_status=$?
+wait
+if [[ $follow_status -eq 0 && "$followed" == *"follow-red"* && "$followed" != *$'\033'* ]]; then
+  pass "tail -f --until stops at the next prompt"
+else
+  fail "tail -f --until stops at the next prompt" "status $follow_status, got: $followed"
+fi
+grepped=$(timeout 1 "$VIA" test-01 tail -f -n 50 --grep 'recent-' 2>/dev/null) || true
+if [[ "$grepped" == *"recent-output"* && "$grepped" != *"old-output"* && "$grepped" != *"follow-red"* ]]; then
+  pass "tail -f --grep"
+else
+  fail "tail -f --grep" "got: $grepped"
+fi
+assert_contains "tail -n --plain" "follow-red" "$VIA" test-01 tail -n 3 --plain
+assert_fails "tail --grep needs -f or -n" "$VIA" test-01 tail --grep x --since
+
+"$VIA" follow-01 run --delim 'mock>' --bg -- bash "$MOCK" 'mock>' 2>/dev/null
+"$VIA" follow-01 wait --timeout 10 >/dev/null 2>&1
+(sleep 0.5; kill "$(cat "$REPLS_DIR/follow-01/pid")" 2>/dev/null) &
+assert_ok "tail -f exits when the session ends" timeout 5 "$VIA" follow-01 tail -f
+wait
+
+# ── grep ─────────────────────────────────────────────────────────────
+echo "# grep"
+
+assert_contains "grep annotates the stanza" "stanza" "$VIA" test-01 grep 'recent-output'
+assert_contains "grep shows the input" ": recent-output ==" "$VIA" test-01 grep '=> recent'
+assert_contains "grep -i" "recent-output" "$VIA" test-01 grep -i 'RECENT-OUTPUT'
+assert_contains "grep -C marks context lines" "-mock>" "$VIA" test-01 grep -C 1 '=> recent-output'
+assert_contains "grep across sessions" "== test-01: stanza" "$VIA" grep 'recent-output'
+assert_fails "grep exits 1 without matches" "$VIA" test-01 grep 'no-such-output-anywhere'
+
+# ── stanzas ──────────────────────────────────────────────────────────
+echo "# stanzas"
+
+assert_contains "stanzas lists inputs" "recent-output" "$VIA" test-01 stanzas
+last_input=$("$VIA" test-01 stanzas | tail -n 1 | awk '{print $NF}')
+assert_contains "tail --stanza -1 is the last exchange" "$last_input" "$VIA" test-01 tail --stanza -1
+first_input=$("$VIA" test-01 stanzas | sed -n 2p | awk '{print $NF}')
+assert_contains "tail --stanza 1 is the first exchange" "$first_input" "$VIA" test-01 tail --stanza 1
+assert_fails "tail --stanza out of range" "$VIA" test-01 tail --stanza 100000
+assert_fails "tail --stanza with -f" "$VIA" test-01 tail --stanza 1 -f
+
+# ── output budget ────────────────────────────────────────────────────
+echo "# output budget"
+
+budget_output=$("$VIA" test-01 --max-lines 6 ':long 100' 2>&1)
+assert_contains "--max-lines elides the middle" "lines omitted, see via test-01 output" printf '%s\n' "$budget_output"
+assert_contains "--max-lines keeps the tail" "line 100:" printf '%s\n' "$budget_output"
+if [[ $(printf '%s\n' "$budget_output" | wc -l) -le 7 ]]; then
+  pass "--max-lines bounds the output"
+else
+  fail "--max-lines bounds the output" "got $(printf '%s\n' "$budget_output" | wc -l) lines"
+fi
+omitted_cmd=$(printf '%s\n' "$budget_output" | grep -o 'output [0-9]* [0-9]*')
+# shellcheck disable=SC2086
+assert_contains "output OFFSET LEN prints the omitted lines" "line 050:" "$VIA" test-01 $omitted_cmd
+assert_contains "tail --max-bytes" "lines omitted" "$VIA" test-01 tail -n 50 --max-bytes 500
+assert_fails "--max-lines with -f" "$VIA" test-01 tail -f --max-lines 5
+assert_fails "output past the end" "$VIA" test-01 output 999999999
+
+# ── lockstep ─────────────────────────────────────────────────────────
+echo "# lockstep"
+
+"$VIA" lock-01 run --delim 'mock>' --error-pattern '^=> boom' --bg -- bash "$MOCK" 'mock>' 2>/dev/null
+"$VIA" lock-01 wait --timeout 10 >/dev/null 2>&1
+script_file="$REPLS_DIR/script.txt"
+printf 'step-one\n\nstep-two\nboom\nstep-never\n' > "$script_file"
+source_status=0
+source_output=$("$VIA" lock-01 source "$script_file" 2>&1) || source_status=$?
+assert_contains "source captures each command" "==> step-two <==" printf '%s\n' "$source_output"
+if [[ $source_status -eq 1 && "$source_output" == *"stopped at command 3 of 4"* && "$source_output" != *"=> step-never"* ]]; then
+  pass "source stops at the first error"
+else
+  fail "source stops at the first error" "status $source_status, got: $source_output"
+fi
+# An indented block goes as one input, ended by a blank line
+printf 'block-start:\n  body-one\n\n  body-two\nafter-block\n' > "$script_file"
+block_output=$("$VIA" lock-01 source --timeout 5 "$script_file" 2>&1) || true
+assert_contains "source sends an indented block whole" "=> block of 3 lines" printf '%s\n' "$block_output"
+assert_contains "source continues after a block" "=> after-block" printf '%s\n' "$block_output"
+lockstep_output=$(printf 'piped-a\npiped-b\n' | "$VIA" lock-01 --lockstep 2>&1)
+assert_contains "--lockstep sends one command per prompt" "==> piped-b <==" printf '%s\n' "$lockstep_output"
+assert_fails "--error-pattern must be a valid regex" "$VIA" lock-02 run --error-pattern '(' --bg -- cat
+stop_session lock-01
+
+# ── restart ──────────────────────────────────────────────────────────
+echo "# restart"
+
+"$VIA" restart-01 run --delim 'mock>' --restart always --max-restarts 1 --backoff 100 --bg -- bash "$MOCK" 'mock>' 2>/dev/null
+"$VIA" restart-01 wait --timeout 10 >/dev/null 2>&1
+"$VIA" restart-01 before-restart >/dev/null 2>&1
+echo ':quit' | "$VIA" restart-01 write 2>/dev/null
+sleep 1
+assert_ok "--restart always relaunches the command" "$VIA" restart-01 wait --timeout 10
+assert_contains "restart is recorded" "exit 0" cat "$REPLS_DIR/restart-01/restarts"
+assert_contains "restart keeps the earlier output" "=> before-restart" "$VIA" restart-01 tail -n 20
+assert_contains "restarted session takes input" "=> after-restart" "$VIA" restart-01 after-restart
+echo ':quit' | "$VIA" restart-01 write 2>/dev/null
+sleep 1
+assert_ok "--max-restarts gives up" test -f "$REPLS_DIR/restart-01/tombstone"
+"$VIA" restart-02 run --delim 'mock>' --restart on-failure --backoff 100 --bg -- bash "$MOCK" 'mock>' 2>/dev/null
+"$VIA" restart-02 wait --timeout 10 >/dev/null 2>&1
+echo ':quit' | "$VIA" restart-02 write 2>/dev/null
+sleep 1
+assert_ok "--restart on-failure ends on a clean exit" test -f "$REPLS_DIR/restart-02/tombstone"
+assert_fails "--restart rejects an unknown policy" "$VIA" restart-03 run --restart sometimes --bg -- cat
+assert_fails "--max-restarts needs --restart" "$VIA" restart-04 run --max-restarts 3 --bg -- cat
+assert_fails "--max-restarts rejects values out of range" "$VIA" restart-05 run --restart always --max-restarts 4294967297 --bg -- cat
+
+# ── hooks ────────────────────────────────────────────────────────────
+echo "# hooks"
+
+# Hook commands are single-quoted and log to a path from the environment, and
+# the supervisor runs from the scratch dir, so a misparsed hook can't write
+# files into the checkout
+export HOOK_LOG="$SCRATCH/hooks.log"
+hook_log="$HOOK_LOG"
+mkdir -p "$XDG_CONFIG_HOME/via"
+echo 'start=echo "user-start $VIA_SESSION" >> "$HOOK_LOG"' > "$XDG_CONFIG_HOME/via/hooks"
+(cd "$SCRATCH" && "$VIA" hook-01 run --delim 'mock>' --bg \
+  --hook 'ready=echo "ready $VIA_EVENT" >> "$HOOK_LOG"' \
+  --hook 'match:^.> kaboom$=echo "match $VIA_MATCH" >> "$HOOK_LOG"' \
+  --hook 'exit=echo "exit $VIA_EXIT_CODE $(test -f "$VIA_SESSION_DIR/stdout" && echo with-output)" >> "$HOOK_LOG"' \
+  -- bash "$MOCK" 'mock>' 2>/dev/null)
+"$VIA" hook-01 wait --timeout 10 >/dev/null 2>&1
+"$VIA" hook-01 kaboom >/dev/null 2>&1
+sleep 0.5
+assert_contains "user start hook runs" "user-start hook-01" cat "$hook_log"
+assert_contains "ready hook runs at the first prompt" "ready ready" cat "$hook_log"
+assert_contains "match hook gets the line" "match => kaboom" cat "$hook_log"
+stop_session hook-01
+sleep 0.5
+assert_contains "exit hook gets the status and output" "exit 0 with-output" cat "$hook_log"
+assert_ok "hooks are stored with the session" grep -q '^ready=' "$REPLS_DIR/hook-01/hooks"
+rm "$XDG_CONFIG_HOME/via/hooks"
+assert_fails "--hook rejects an unknown event" "$VIA" hook-02 run --hook 'boot=true' --bg -- cat
+assert_fails "--hook needs EVENT=COMMAND" "$VIA" hook-03 run --hook 'start' --bg -- cat
+
+# ── launch options ───────────────────────────────────────────────────
+echo "# launch options"
+
+mkdir -p "$SCRATCH/workdir"
+env_file="$SCRATCH/vars.env"
+printf '# comment\nexport FROM_FILE="file value"\n' > "$env_file"
+"$VIA" env-01 run --shell --cwd "$SCRATCH/workdir" --env GREETING=hello --env-file "$env_file" --bg -- bash 2>/dev/null
+"$VIA" env-01 wait --timeout 10 >/dev/null 2>&1
+assert_contains "--cwd sets the working directory" "$SCRATCH/workdir" "$VIA" env-01 pwd
+assert_contains "--env sets a variable" "hello" "$VIA" env-01 'echo $GREETING'
+assert_contains "--env-file sets variables" "file value" "$VIA" env-01 'echo $FROM_FILE'
+assert_contains "VIA_SESSION is set" "env-01" "$VIA" env-01 'echo $VIA_SESSION'
+assert_contains "VIA_SESSION_DIR is set" "$REPLS_DIR/env-01" "$VIA" env-01 'echo $VIA_SESSION_DIR'
+assert_contains "listing shows --cwd" "$SCRATCH/workdir" "$VIA"
+assert_contains "status shows the environment" "GREETING=hello" "$VIA" env-01 status
+stop_session env-01
+"$VIA" env-02 run --shell --clear-env --env KEPT=yes --bg -- bash 2>/dev/null
+"$VIA" env-02 wait --timeout 10 >/dev/null 2>&1
+clear_output=$("$VIA" env-02 'echo "kept=$KEPT home=$HOME"' 2>&1)
+assert_contains "--clear-env drops inherited variables" "kept=yes home=" printf '%s\n' "$clear_output"
+if [[ "$clear_output" != *"home=/"* ]]; then
+  pass "--clear-env leaves HOME unset"
+else
+  fail "--clear-env leaves HOME unset" "got: $clear_output"
+fi
+stop_session env-02
+assert_fails "--cwd must exist" "$VIA" env-03 run --cwd "$SCRATCH/missing" --bg -- cat
+assert_fails "--env needs KEY=VALUE" "$VIA" env-04 run --env NOVALUE --bg -- cat
+
+# ── attach ───────────────────────────────────────────────────────────
+echo "# attach"
+
+assert_fails "attach needs a terminal" "$VIA" test-01 attach </dev/null
+assert_fails "attach rejects a bad detach key" "$VIA" test-01 attach --detach-key 'ctrl-ab'
+if command -v python3 >/dev/null; then
+  # Drive attach from a pseudo-terminal: type a line, then detach with Ctrl-]
+  attach_output=$(python3 - "$VIA" <<'PY'
+import os, pty, select, sys, time
+pid, fd = pty.fork()
+if pid == 0:
+    os.execv(sys.argv[1], [sys.argv[1], "test-01", "attach"])
+out = b""
+def drain(secs):
+    global out
+    end = time.time() + secs
+    while time.time() < end:
+        if select.select([fd], [], [], 0.1)[0]:
+            try:
+                out += os.read(fd, 4096)
+            except OSError:
+                return
+drain(0.5)
+os.write(fd, b"typed-in-attach\r")
+drain(1)
+os.write(fd, b"\x1d")
+drain(0.5)
+_, status = os.waitpid(pid, 0)
+sys.stdout.write(out.decode(errors="replace") + "exit=%d\n" % os.waitstatus_to_exitcode(status))
+PY
+)
+  assert_contains "attach mirrors output of typed input" "=> typed-in-attach" printf '%s\n' "$attach_output"
+  assert_contains "attach detaches with Ctrl-]" "detached from 'test-01'" printf '%s\n' "$attach_output"
+  assert_contains "attach exits cleanly" "exit=0" printf '%s\n' "$attach_output"
+  assert_ok "session survives detaching" "$VIA" test-01 after-attach
+fi
+
+# ── mcp ──────────────────────────────────────────────────────────────
+echo "# mcp"
+
+mcp_requests='{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-06-18"}}
+{"jsonrpc":"2.0","method":"notifications/initialized"}
+{"jsonrpc":"2.0","id":2,"method":"tools/list"}
+{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"eval","arguments":{"session":"test-01","input":"from-mcp"}}}
+{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"eval","arguments":{"session":"nonexistent","input":"x"}}}'
+mcp_output=$(echo "$mcp_requests" | "$VIA" mcp 2>&1)
+assert_contains "mcp initialize" '"protocolVersion":"2025-06-18"' printf '%s\n' "$mcp_output"
+assert_contains "mcp tools/list" '"name":"eval"' printf '%s\n' "$mcp_output"
+assert_contains "mcp eval" '=> from-mcp' printf '%s\n' "$mcp_output"
+assert_contains "mcp structured error" '"error":"not_found"' printf '%s\n' "$mcp_output"
+# An older client gets its own version back, and no structuredContent
+mcp_old=$(printf '%s\n' '{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2024-11-05"}}' \
+  '{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"eval","arguments":{"session":"nonexistent","input":"x"}}}' \
+  | "$VIA" mcp 2>&1)
+if [[ "$mcp_old" == *'"protocolVersion":"2024-11-05"'* && "$mcp_old" != *structuredContent* ]]; then
+  pass "mcp negotiates an older protocol version"
+else
+  fail "mcp negotiates an older protocol version" "got: $mcp_old"
+fi
+
+mcp_bad=$(printf '%s\n' '{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}' \
+  '{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"eval","arguments":{"session":"test-01","input":"bad-timeout","timeout":-1}}}' \
+  '{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"wait","arguments":{"session":"test-01","timeout":1e300}}}' \
+  | "$VIA" mcp 2>&1)
+if [[ "$mcp_bad" == *'"id":2'*'invalid timeout'* && "$mcp_bad" == *'"id":3'*'invalid timeout'* ]]; then
+  pass "mcp rejects invalid timeouts"
+else
+  fail "mcp rejects invalid timeouts" "got: $mcp_bad"
+fi
+assert_fails "shorthand rejects a negative timeout" "$VIA" test-01 --timeout -1 neg-timeout
+if grep -q "neg-timeout\|bad-timeout" "$REPLS_DIR/test-01/stdout"; then
+  fail "invalid timeouts send nothing" "input reached the session"
+else
+  pass "invalid timeouts send nothing"
+fi
+
+# start_session launches the supervisor from a via process of its own
+mcp_start=$(printf '%s\n' '{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}' \
+  "{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"tools/call\",\"params\":{\"name\":\"start_session\",\"arguments\":{\"name\":\"mcp-start\",\"command\":[\"bash\",\"$MOCK\",\"mock>\"],\"delim\":\"mock>\",\"group\":\"mcp\"}}}" \
+  | "$VIA" mcp 2>&1)
+assert_contains "mcp start_session" "started session 'mcp-start'" printf '%s\n' "$mcp_start"
+"$VIA" mcp-start wait --timeout 10 2>/dev/null || true
+assert_contains "mcp start_session passes options" "=> via-spawned" "$VIA" mcp-start via-spawned
+assert_contains "mcp start_session keeps group" "mcp" cat "$REPLS_DIR/mcp-start/group"
+mcp_stop=$(printf '%s\n' '{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}' \
+  '{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"stop_session","arguments":{"session":"mcp-start"}}}' \
+  '{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"tail","arguments":{"session":"mcp-start","lines":5}}}' \
+  | "$VIA" mcp 2>&1)
+assert_contains "mcp stop_session" "stopped session 'mcp-start'" printf '%s\n' "$mcp_stop"
+if [[ "$mcp_stop" == *'"id":3'*'via-spawned'* && "$mcp_stop" != *'"id":3'*'"isError":true'* ]]; then
+  pass "mcp tail reads an ended session"
+else
+  fail "mcp tail reads an ended session" "got: $mcp_stop"
+fi
+"$VIA" gc 'mcp-start' >/dev/null 2>&1 || true
+
+# ── broadcast ────────────────────────────────────────────────────────
+echo "# broadcast"
+
+"$VIA" test-02 run --delim 'mock>' --group pair --bg -- bash "$MOCK" 'mock>' 2>/dev/null
+"$VIA" test-02 wait --timeout 10 2>/dev/null
+each_output=$("$VIA" --each 'test-0*' each-check 2>&1) || true
+assert_contains "--each header" "==> test-02 <==" printf '%s\n' "$each_output"
+assert_contains "--each output" "=> each-check" printf '%s\n' "$each_output"
+assert_contains "--each summary" "test-01  ok" printf '%s\n' "$each_output"
+assert_contains "--each @group" "==> test-02 <==" "$VIA" --each @pair group-check
+assert_fails "--each no match" "$VIA" --each 'nomatch-*' hello
+# A session without a prompt: --idle completes it, and output that stops
+# mid-line still ends before the next header
+"$VIA" each-raw run --bg -- bash -c 'while read -r l; do printf "raw-%s" "$l"; done' 2>/dev/null
+"$VIA" --each 'each-raw' nodelim > "$SCRATCH/each.out" 2> "$SCRATCH/each.err" || true
+if grep -q "no stored delimiter" "$SCRATCH/each.err" && ! grep -q "no stored delimiter" "$SCRATCH/each.out"; then
+  pass "--each reports failures on stderr"
+else
+  fail "--each reports failures on stderr" "stdout: $(cat "$SCRATCH/each.out") stderr: $(cat "$SCRATCH/each.err")"
+fi
+"$VIA" --each 'each-raw' --idle 300 idle-each > "$SCRATCH/each.out" 2>&1 || true
+if grep -qx "raw-idle-each" "$SCRATCH/each.out"; then
+  pass "--each --idle ends a partial line"
+else
+  fail "--each --idle ends a partial line" "got: $(cat "$SCRATCH/each.out")"
+fi
+kill "$(cat "$REPLS_DIR/each-raw/pid")" 2>/dev/null || true
+stop_session test-02
+
+# ── pipe ─────────────────────────────────────────────────────────────
+echo "# pipe"
+
+"$VIA" test-01 pipe-source >/dev/null 2>&1
+assert_contains "pipe last stanza" "=> => pipe-source" "$VIA" pipe test-01 test-01
+"$VIA" test-01 pipe-source >/dev/null 2>&1
+assert_contains "pipe --regex capture" "=> pipe-source" "$VIA" pipe test-01 test-01 --regex '=> (.*)'
+assert_fails "pipe --regex no match" "$VIA" pipe test-01 test-01 --regex 'no-such-text'
+
+# ── export ───────────────────────────────────────────────────────────
+echo "# export"
+
+"$VIA" test-01 ':color warn' >/dev/null 2>&1
+assert_contains "export markdown heading" '. `hello`' "$VIA" test-01 export --format markdown
+assert_contains "export asciicast header" '"version":2' "$VIA" test-01 export --format asciicast
+assert_contains "export asciicast keeps colors" '\u001b[31mwarn' "$VIA" test-01 export --format asciicast
+assert_contains "export html colors" '<span style="color: #cd0000">warn</span>' "$VIA" test-01 export --format html
+export_file="$REPLS_DIR/transcript.md"
+"$VIA" test-01 export --format md -o "$export_file" 2>/dev/null
+assert_contains "export -o writes file" '`:color warn`' cat "$export_file"
+assert_fails "export unknown format" "$VIA" test-01 export --format pdf
+
+# ── record & replay ──────────────────────────────────────────────────
+echo "# record & replay"
+
+assert_contains "record captures exchanges" '"input": "hello"' "$VIA" test-01 record
+record_file="$REPLS_DIR/test-01.json"
+"$VIA" test-01 record -o "$record_file" 2>/dev/null
+assert_contains "record strips echo" '"output": "=> hello' cat "$record_file"
+
+"$VIA" replay-01 run --delim 'calc>' --bg -- "$VIA" replay "$DIR/fixtures/calc.json" --speed 0 2>/dev/null
+"$VIA" replay-01 wait --timeout 10 >/dev/null 2>&1
+assert_contains "replay answers recorded input" "42" "$VIA" replay-01 '6 * 7'
+assert_contains "replay out of order" "division by zero" "$VIA" replay-01 '1 / 0'
+assert_contains "replay unknown input" "no recorded output for 'nope'" "$VIA" replay-01 nope
+kill "$(cat "$REPLS_DIR/replay-01/pid")" 2>/dev/null || true
+
+"$VIA" replay-02 run --delim 'mock>' --bg -- "$VIA" replay "$record_file" --speed 0 2>/dev/null
+"$VIA" replay-02 wait --timeout 10 >/dev/null 2>&1
+assert_contains "replay a recording" "=> hello" "$VIA" replay-02 hello
+kill "$(cat "$REPLS_DIR/replay-02/pid")" 2>/dev/null || true
+
+# Multi-line input is logged as one exchange, and replayed once all of it arrives
+start_session rec-01 'mock>'
+printf 'first\nsecond\n' | "$VIA" rec-01 >/dev/null 2>&1
+sleep 0.3
+"$VIA" rec-01 record -o "$SCRATCH/multi.json" 2>/dev/null
+stop_session rec-01
+assert_contains "record keeps multi-line input whole" '"input": "first\nsecond"' cat "$SCRATCH/multi.json"
+"$VIA" replay-03 run --delim 'mock>' --bg -- "$VIA" replay "$SCRATCH/multi.json" --speed 0 2>/dev/null
+"$VIA" replay-03 wait --timeout 10 >/dev/null 2>&1
+printf 'first\nsecond\n' | "$VIA" replay-03 >/dev/null 2>&1
+sleep 0.3
+replay_output=$("$VIA" replay-03 tail -n 5 2>&1)
+if [[ "$replay_output" == *"=> second"* && "$replay_output" != *"no recorded output"* ]]; then
+  pass "replay a multi-line exchange"
+else
+  fail "replay a multi-line exchange" "got: $replay_output"
+fi
+kill "$(cat "$REPLS_DIR/replay-03/pid")" 2>/dev/null || true
+sleep 0.5
+
 # ── session listing ──────────────────────────────────────────────────
 echo "# session listing"
 
@@ -145,6 +675,47 @@ assert_contains "auto name listed" "bash-00" "$VIA" --simple
 echo ':quit' | "$VIA" bash-00 write 2>/dev/null || true
 sleep 0.5
 
+# ── session naming ───────────────────────────────────────────────────
+echo "# session naming"
+
+assert_stderr_contains "invalid name rejected" "invalid session name" "$VIA" ../escape run --bg -- bash "$MOCK"
+assert_stderr_contains "slash in name rejected" "invalid session name" "$VIA" a/b path
+assert_stderr_contains "claimed name refused" "already exists" "$VIA" test-01 run --bg -- bash "$MOCK"
+
+mkdir -p "$SCRATCH/checkouts/one/app" "$SCRATCH/checkouts/two/app"
+assert_contains "'.' uses basename" "/app" sh -c "cd '$SCRATCH/checkouts/one/app' && '$VIA' . path"
+one=$(cd "$SCRATCH/checkouts/one/app" && VIA_DOT=path "$VIA" . path)
+two=$(cd "$SCRATCH/checkouts/two/app" && VIA_DOT=path "$VIA" . path)
+if [[ "$one" == */app-* && "$one" != "$two" ]]; then
+  pass "VIA_DOT=path distinguishes checkouts"
+else
+  fail "VIA_DOT=path distinguishes checkouts" "got '$one' and '$two'"
+fi
+echo "pinned" > "$SCRATCH/checkouts/two/.via-session"
+assert_contains "'.' reads .via-session marker" "/pinned" sh -c "cd '$SCRATCH/checkouts/two/app' && '$VIA' . path"
+
+# ── session directory permissions ────────────────────────────────────
+echo "# session directory permissions"
+
+session_mode=$(stat -c %a "$REPLS_DIR/test-01")
+if [[ "$session_mode" == "700" ]]; then
+  pass "session dir is private"
+else
+  fail "session dir is private" "mode $session_mode"
+fi
+
+open_dir="$(mktemp -d)"
+chmod 755 "$open_dir"
+assert_stderr_contains "world-readable base dir refused" "accessible to other users" env REPLS_DIR="$open_dir" "$VIA"
+assert_ok "shared mode creates group dir" env REPLS_DIR="$open_dir/shared" VIA_SHARED=1 "$VIA"
+shared_mode=$(stat -c %a "$open_dir/shared")
+if [[ "$shared_mode" == "2770" ]]; then
+  pass "shared base dir is group-only"
+else
+  fail "shared base dir is group-only" "mode $shared_mode"
+fi
+rm -rf "$open_dir"
+
 # ── long output ──────────────────────────────────────────────────────
 echo "# long output"
 
@@ -169,8 +740,8 @@ fi
 
 stop_session test-01
 
-# ── --bg cleanup ─────────────────────────────────────────────────────
-echo "# --bg cleanup"
+# ── tombstones ───────────────────────────────────────────────────────
+echo "# tombstones"
 
 start_session test-bg 'mock>'
 if [ -d "$REPLS_DIR/test-bg" ]; then
@@ -178,13 +749,53 @@ if [ -d "$REPLS_DIR/test-bg" ]; then
 else
   fail "--bg session dir exists while running" "dir missing"
 fi
-echo ':quit' | "$VIA" test-bg write 2>/dev/null || true
-sleep 1
-if [ ! -d "$REPLS_DIR/test-bg" ]; then
-  pass "--bg session cleaned up after exit"
+assert_contains "status of a running session" "running (pid" "$VIA" test-bg status
+"$VIA" test-bg last-words >/dev/null 2>&1
+# A client waiting when the session ends fails instead of timing out
+wait_start=$SECONDS
+wait_status=0
+( sleep 0.5; echo ':quit' | "$VIA" test-bg write 2>/dev/null ) &
+wait_output=$("$VIA" test-bg wait --until 'never-printed' --timeout 20 2>&1) || wait_status=$?
+if [[ $wait_status -ne 0 && "$wait_output" == *"has ended"* && $((SECONDS - wait_start)) -lt 10 ]]; then
+  pass "waiting client fails fast when the session ends"
 else
-  fail "--bg session cleaned up after exit" "dir still exists"
+  fail "waiting client fails fast when the session ends" "status $wait_status, got: $wait_output"
 fi
+sleep 0.5
+assert_ok "ended session leaves a tombstone" test -f "$REPLS_DIR/test-bg/tombstone"
+assert_ok "tombstone replaces the input FIFO" test ! -e "$REPLS_DIR/test-bg/stdin"
+assert_ok "tombstone keeps the transcript" test -f "$REPLS_DIR/test-bg/stdout"
+assert_contains "status shows the exit status" "exited with status 0" "$VIA" test-bg status
+assert_contains "status shows the final output" "=> last-words" "$VIA" test-bg status
+assert_contains "listing shows the ended state" "exited 0" "$VIA"
+assert_stderr_contains "input to an ended session fails" "has ended" "$VIA" test-bg more-input
+assert_contains "tail -f on an ended session shows its output and returns" "=> last-words" timeout 5 "$VIA" test-bg tail -f
+assert_contains "grep searches an ended session" "last-words" "$VIA" test-bg grep last-words
+assert_contains "export works on an ended session" "last-words" "$VIA" test-bg export --format markdown
+start_session test-bg 'mock>'
+assert_contains "an ended session's name can be reclaimed" "running (pid" "$VIA" test-bg status
+stop_session test-bg
+# Several runs reclaiming the same ended name: exactly one gets it
+start_session race-01 'mock>'
+stop_session race-01
+for i in 1 2 3 4; do
+  ( "$VIA" race-01 run --delim 'mock>' --bg -- bash "$MOCK" 'mock>' >/dev/null 2>&1 && echo won >> "$SCRATCH/race.out" || true ) &
+done
+wait
+if [[ $(grep -c won "$SCRATCH/race.out") -eq 1 ]]; then
+  pass "only one run reclaims an ended name"
+else
+  fail "only one run reclaims an ended name" "$(grep -c won "$SCRATCH/race.out") runs claimed it"
+fi
+assert_contains "the reclaimed session runs" "=> after-race" "$VIA" race-01 after-race
+stop_session race-01
+assert_contains "gc removes ended sessions" "test-bg" "$VIA" gc 'test-*'
+assert_ok "gc removes the directory" test ! -e "$REPLS_DIR/test-bg"
+"$VIA" test-rm run --rm --delim 'mock>' --bg -- bash "$MOCK" 'mock>' 2>/dev/null
+"$VIA" test-rm wait --timeout 10 >/dev/null 2>&1
+stop_session test-rm
+sleep 0.5
+assert_ok "--rm removes the session when it ends" test ! -e "$REPLS_DIR/test-rm"
 
 # ── error cases (no session running) ─────────────────────────────────
 echo "# error cases"